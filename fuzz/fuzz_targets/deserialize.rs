@@ -0,0 +1,20 @@
+#![no_main]
+
+use crawdad::{MpTrie, Trie};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // A deserialized trie must be queryable without panicking.
+    if let Ok((trie, _)) = Trie::try_deserialize_from_slice(data) {
+        assert!(trie.validate().is_ok());
+        trie.exact_match("世界".chars());
+        trie.common_prefix_search("世界中".chars()).count();
+        trie.iter().count();
+    }
+    if let Ok((trie, _)) = MpTrie::try_deserialize_from_slice(data) {
+        assert!(trie.validate().is_ok());
+        trie.exact_match("世界".chars());
+        trie.common_prefix_search("世界中".chars()).count();
+        trie.iter().count();
+    }
+});
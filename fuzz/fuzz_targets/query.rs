@@ -0,0 +1,22 @@
+#![no_main]
+
+use crawdad::{MpTrie, Trie};
+use libfuzzer_sys::fuzz_target;
+
+const KEYS: &[&str] = &["世界", "世界中", "世論調査", "統計調査", "a", "ab", "abc"];
+
+fuzz_target!(|text: &str| {
+    let trie = Trie::from_keys(KEYS).unwrap();
+    let mptrie = MpTrie::from_keys(KEYS).unwrap();
+    let expected = KEYS.iter().position(|&k| k == text).map(|i| i as u32);
+    assert_eq!(trie.exact_match(text.chars()), expected);
+    assert_eq!(mptrie.exact_match(text.chars()), expected);
+
+    let haystack: Vec<char> = text.chars().collect();
+    for i in 0..haystack.len() {
+        let suffix = haystack[i..].iter().copied();
+        assert!(trie
+            .common_prefix_search(suffix.clone())
+            .eq(mptrie.common_prefix_search(suffix)));
+    }
+});
@@ -1,27 +1,64 @@
+//! Builder of tries.
 use crate::errors::{CrawdadError, Result};
+use crate::keys::KeyStore;
 use crate::mapper::CodeMapper;
+use crate::payloads::PayloadStore;
 use crate::{utils, MpTrie, Node, Trie};
 use crate::{END_CODE, END_MARKER, INVALID_IDX, MAX_VALUE, OFFSET_MASK};
 
 use core::cmp::Ordering;
+use core::mem;
 
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
 // The default parameter for free blocks to be searched in `find_base`.
 const DEFAULT_NUM_FREE_BLOCKS: u32 = 16;
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 struct Record {
     key: Vec<char>,
     value: u32,
 }
 
-#[derive(Default, Debug, PartialEq, Eq)]
+impl Record {
+    fn new(key: &str, value: u32) -> Self {
+        // Reserves an extra slot for END_MARKER pushed in make_prefix_free().
+        let mut chars = Vec::with_capacity(key.chars().count() + 1);
+        chars.extend(key.chars());
+        Self { key: chars, value }
+    }
+}
+
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
 struct Suffix {
     key: Vec<char>,
     value: u32,
 }
 
+/// Builder of [`Trie`] and [`MpTrie`] with configurable options.
+///
+/// A configured builder can be cloned to build multiple tries with the same options.
+/// Cloning a builder before building is cheap because it copies only the options.
+///
+/// Invalid input is reported as an error from a build or release method, which returns
+/// [`Result`] like [`Trie::from_keys()`], a shorthand of the same chain.
+///
+/// # Examples
+///
+/// ```
+/// use crawdad::Builder;
+///
+/// let keys = vec!["世界", "世界中", "国民"];
+/// let trie = Builder::new()
+///     .build_from_keys(&keys)
+///     .unwrap()
+///     .release_trie()
+///     .unwrap();
+///
+/// assert_eq!(trie.exact_match("世界中".chars()), Some(1));
+/// ```
+#[derive(Clone)]
 pub struct Builder {
     records: Vec<Record>,
     mapper: CodeMapper,
@@ -31,6 +68,15 @@ pub struct Builder {
     head_idx: u32,
     block_len: u32,
     num_free_blocks: u32,
+    // Upper bound of num_nodes, which is lowered only in tests.
+    max_num_nodes: u32,
+    identity_mapping: bool,
+    code_order: CodeOrder,
+    min_prefix_len: usize,
+    keep_keys: bool,
+    max_key_chars: usize,
+    value_offset: u32,
+    payloads: Option<PayloadStore>,
 }
 
 impl Default for Builder {
@@ -44,78 +90,613 @@ impl Default for Builder {
             head_idx: 0,
             block_len: 0,
             num_free_blocks: DEFAULT_NUM_FREE_BLOCKS,
+            max_num_nodes: OFFSET_MASK,
+            identity_mapping: false,
+            code_order: CodeOrder::ByFrequency,
+            min_prefix_len: 0,
+            keep_keys: false,
+            max_key_chars: usize::MAX,
+            value_offset: 0,
+            payloads: None,
         }
     }
 }
 
 impl Builder {
+    /// Creates a new [`Builder`] with the default configuration.
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Enables the minimal-prefix form, which is required for [`Builder::release_mptrie()`].
     #[allow(clippy::missing_const_for_fn)]
     pub fn minimal_prefix(mut self) -> Self {
         self.suffixes = Some(vec![]);
         self
     }
 
+    /// Enables the minimal-prefix form like [`Builder::minimal_prefix()`],
+    /// keeping at least the first `min` characters of each key in the double array.
+    ///
+    /// Only the rest of a key is spilled into the tail storage.
+    /// A larger value increases the number of nodes and decreases the size of tails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Builder;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Builder::new()
+    ///     .minimal_prefix_len(2)
+    ///     .build_from_keys(&keys)
+    ///     .unwrap()
+    ///     .release_mptrie()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(trie.exact_match("国民".chars()), Some(2));
+    /// ```
+    #[allow(clippy::missing_const_for_fn)]
+    pub fn minimal_prefix_len(mut self, min: usize) -> Self {
+        self.suffixes = Some(vec![]);
+        self.min_prefix_len = min;
+        self
+    }
+
+    /// Uses the identity code mapping for ASCII-only keys.
+    ///
+    /// Each ASCII character `c` is mapped to `c + 1` without frequency-based reordering,
+    /// and the mapping table has only 128 entries.
+    /// If keys contain a non-ASCII character, the frequency-based mapping is used instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Builder;
+    ///
+    /// let keys = vec!["ab", "abc", "bc"];
+    /// let trie = Builder::new()
+    ///     .identity_mapping()
+    ///     .build_from_keys(&keys)
+    ///     .unwrap()
+    ///     .release_trie()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(trie.exact_match("abc".chars()), Some(1));
+    /// ```
+    pub const fn identity_mapping(mut self) -> Self {
+        self.identity_mapping = true;
+        self
+    }
+
+    /// Sets the order of characters in which codes are assigned.
+    ///
+    /// [`CodeOrder::ByFrequency`] is the default.
+    /// Both orders are deterministic for the same set of keys.
+    /// This option is ignored if [`Builder::identity_mapping()`] applies.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::{Builder, CodeOrder};
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Builder::new()
+    ///     .code_order(CodeOrder::ByCodepoint)
+    ///     .build_from_keys(&keys)
+    ///     .unwrap()
+    ///     .release_trie()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(trie.exact_match("世界中".chars()), Some(1));
+    /// ```
+    pub const fn code_order(mut self, order: CodeOrder) -> Self {
+        self.code_order = order;
+        self
+    }
+
+    /// Keeps the original keys in the resulting [`Trie`] for [`Trie::key()`].
+    ///
+    /// The keys are stored in a concatenated buffer with offsets,
+    /// plus a table from values to keys sorted by values, both proportional to the number of keys.
+    /// The kept keys are not serialized.
+    /// This option is ignored by [`Builder::release_mptrie()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Builder;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Builder::new()
+    ///     .keep_keys()
+    ///     .build_from_keys(&keys)
+    ///     .unwrap()
+    ///     .release_trie()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(trie.key(1), Some("世界中"));
+    /// ```
+    pub const fn keep_keys(mut self) -> Self {
+        self.keep_keys = true;
+        self
+    }
+
+    /// Rejects keys longer than `limit` characters.
+    ///
+    /// This bounds the depth of the resulting trie, e.g., against a huge line
+    /// accidentally contained in an input file.
+    /// A build method returns [`CrawdadError::KeyTooLong`] for the first such key
+    /// in the input order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::errors::CrawdadError;
+    /// use crawdad::Builder;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let result = Builder::new().max_key_chars(2).build_from_keys(&keys);
+    ///
+    /// match result {
+    ///     Err(CrawdadError::KeyTooLong(e)) => {
+    ///         assert_eq!((e.index(), e.len(), e.limit()), (1, 3, 2));
+    ///     }
+    ///     _ => panic!("the key must be too long"),
+    /// }
+    /// ```
+    pub const fn max_key_chars(mut self, limit: usize) -> Self {
+        self.max_key_chars = limit;
+        self
+    }
+
+    /// Shifts the values associated by [`Builder::build_from_keys()`] by `offset`,
+    /// i.e., values in `[offset..offset+n-1]` are associated with `n` keys.
+    ///
+    /// This leaves values less than `offset`, e.g., `0`, free for sentinels.
+    /// The build fails if `offset + n - 1` exceeds the maximum value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Builder;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Builder::new()
+    ///     .value_offset(1)
+    ///     .build_from_keys(&keys)
+    ///     .unwrap()
+    ///     .release_trie()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(trie.exact_match("世界".chars()), Some(1));
+    /// assert_eq!(trie.exact_match("国民".chars()), Some(3));
+    /// ```
+    pub const fn value_offset(mut self, offset: u32) -> Self {
+        self.value_offset = offset;
+        self
+    }
+
+    /// Takes over the node array of a trie, e.g., one emptied by [`Trie::clear()`],
+    /// so that the construction reuses its allocation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::{Builder, Trie};
+    ///
+    /// let mut trie = Trie::from_keys(["世界", "世界中"]).unwrap();
+    /// trie.clear();
+    ///
+    /// let trie = Builder::new()
+    ///     .reuse_buffers(trie)
+    ///     .build_from_keys(["国民", "市民"])
+    ///     .unwrap()
+    ///     .release_trie()
+    ///     .unwrap();
+    /// assert_eq!(trie.exact_match("市民".chars()), Some(1));
+    /// ```
+    #[allow(clippy::missing_const_for_fn)]
+    pub fn reuse_buffers(mut self, trie: Trie) -> Self {
+        self.nodes = trie.nodes;
+        self
+    }
+
+    /// Builds a trie from input keys.
+    ///
+    /// Values in `[0..n-1]` will be associated with keys in the input order,
+    /// where `n` is the number of keys.
+    /// The values are shifted by [`Builder::value_offset()`] if given.
+    /// Keys can be given in any order; they are sorted by the builder.
+    /// If they are sorted by the [`Ord`] of [`str`] (e.g., with [`prepare_keys()`](crate::prepare_keys)),
+    /// the values follow the lexicographical order.
+    ///
+    /// # Errors
+    ///
+    /// See [`Trie::from_keys()`].
     pub fn build_from_keys<I, K>(self, keys: I) -> Result<Self>
     where
         I: IntoIterator<Item = K>,
         K: AsRef<str>,
     {
+        let offset = self.value_offset;
         self.build_from_records(
             keys.into_iter()
                 .enumerate()
-                .map(|(i, k)| (k, i.try_into().unwrap())),
+                .map(|(i, k)| (k, index_to_value(i, offset))),
         )
     }
 
+    /// Builds a trie from input keys and their associated values given as separate slices.
+    ///
+    /// The `i`-th value is associated with the `i`-th key.
+    ///
+    /// # Errors
+    ///
+    /// [`CrawdadError`] will be returned when `keys` and `values` have different lengths.
+    /// See [`Trie::from_records()`] for the other cases.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Builder;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let values = vec![2, 3, 2];
+    /// let trie = Builder::new()
+    ///     .build_from_keys_and_values(&keys, &values)
+    ///     .unwrap()
+    ///     .release_trie()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(trie.exact_match("世界中".chars()), Some(3));
+    /// ```
+    pub fn build_from_keys_and_values<K>(self, keys: &[K], values: &[u32]) -> Result<Self>
+    where
+        K: AsRef<str>,
+    {
+        if keys.len() != values.len() {
+            return Err(CrawdadError::input(
+                "keys and values must have the same length.",
+            ));
+        }
+        self.build_from_records(keys.iter().zip(values.iter().copied()))
+    }
+
+    /// Builds a trie from input keys with byte payloads attached,
+    /// which are retrieved by [`Trie::payload()`].
+    ///
+    /// Values in `[0..n-1]` will be associated with keys in the input order
+    /// as the indices of their payloads, where `n` is the number of keys.
+    /// [`Builder::value_offset()`] is not applied, and
+    /// the payloads are ignored by [`Builder::release_mptrie()`].
+    ///
+    /// # Errors
+    ///
+    /// [`CrawdadError`] will be returned when the total length of payloads exceeds `u32::MAX`.
+    /// See [`Trie::from_keys()`] for the other cases.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Builder;
+    ///
+    /// let records = vec![("世界", "せかい"), ("国民", "こくみん")];
+    /// let trie = Builder::new()
+    ///     .build_from_payloads(records)
+    ///     .unwrap()
+    ///     .release_trie()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(trie.exact_match("国民".chars()), Some(1));
+    /// assert_eq!(trie.payload("世界".chars()), Some("せかい".as_bytes()));
+    /// ```
+    pub fn build_from_payloads<I, K, P>(self, records: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (K, P)>,
+        K: AsRef<str>,
+        P: AsRef<[u8]>,
+    {
+        let records: Vec<_> = records.into_iter().collect();
+        let payloads = PayloadStore::new(records.iter().map(|(_, p)| p))?;
+        let mut builder = self.build_from_records(
+            records
+                .iter()
+                .enumerate()
+                .map(|(i, (k, _))| (k, index_to_value(i, 0))),
+        )?;
+        builder.payloads = Some(payloads);
+        Ok(builder)
+    }
+
+    /// Builds a trie from input records.
+    ///
+    /// # Errors
+    ///
+    /// See [`Trie::from_records()`].
     pub fn build_from_records<I, K>(mut self, records: I) -> Result<Self>
     where
         I: IntoIterator<Item = (K, u32)>,
         K: AsRef<str>,
     {
+        self.payloads = None;
+        self.load_records(records)?;
+        self.build_from_sorted_records()
+    }
+
+    /// Sets input records sorted by keys.
+    fn load_records<I, K>(&mut self, records: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (K, u32)>,
+        K: AsRef<str>,
+    {
+        let max_key_chars = self.max_key_chars;
         self.records = records
             .into_iter()
-            .map(|(k, v)| Record {
-                key: k.as_ref().chars().collect(),
-                value: v,
+            .enumerate()
+            .map(|(i, (k, v))| {
+                let record = Record::new(k.as_ref(), v);
+                check_key_chars(i, &record, max_key_chars)?;
+                Ok(record)
             })
-            .collect();
-
+            .collect::<Result<_>>()?;
         self.records.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        Ok(())
+    }
+
+    /// Estimates the size of a trie built from input keys without constructing it.
+    ///
+    /// It runs the placement of nodes in the same way as a build method,
+    /// but only on a bitmap of one bit per element instead of the node array of eight bytes
+    /// per element, and skips materializing the tails of [`MpTrie`] and the kept keys.
+    /// The estimate is exact, i.e., the same as the statistics of the trie
+    /// returned by [`Builder::release_trie()`] or [`Builder::release_mptrie()`]
+    /// (depending on [`Builder::minimal_prefix()`]), so it can be used to reject
+    /// an over-scale input before allocating the trie.
+    /// It takes time comparable to a build.
+    ///
+    /// # Errors
+    ///
+    /// See [`Trie::from_keys()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::{Builder, Trie};
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let estimate = Builder::new().estimate(&keys).unwrap();
+    ///
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    /// assert_eq!(estimate.num_elems, trie.num_elems());
+    /// assert_eq!(estimate.heap_bytes, trie.heap_bytes());
+    /// ```
+    pub fn estimate<I, K>(mut self, keys: I) -> Result<SizeEstimate>
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<str>,
+    {
+        let offset = self.value_offset;
+        self.load_records(
+            keys.into_iter()
+                .enumerate()
+                .map(|(i, k)| (k, index_to_value(i, offset))),
+        )?;
+        self.prepare_records()?;
+
+        let mut fixed = FixedMap::new(self.block_len);
+        let mut num_used = 1;
+        let mut leaves = vec![];
+        let mut stack = vec![(0, self.records.len(), 0)];
+        while let Some((spos, epos, depth)) = stack.pop() {
+            if self.is_leaf_range(spos, epos, depth) {
+                leaves.push((spos, depth));
+                continue;
+            }
+            self.fetch_labels(spos, epos, depth);
+            let base = fixed.find_base(&self.labels);
+            if base >= fixed.num_nodes {
+                let new_len = fixed.num_nodes + self.block_len;
+                if self.max_num_nodes < new_len {
+                    return Err(CrawdadError::too_many_nodes(new_len, self.max_num_nodes));
+                }
+                fixed.enlarge(self.block_len, self.num_free_blocks);
+            }
+            for &label in &self.labels {
+                fixed.fix(base ^ label);
+            }
+            num_used += self.labels.len();
+            // Children are popped in the same order as arrange_node().
+            let num_pushed = stack.len();
+            let mut i1 = spos;
+            for i2 in spos + 1..epos {
+                if self.records[i1].key[depth] != self.records[i2].key[depth] {
+                    stack.push((i1, i2, depth + 1));
+                    i1 = i2;
+                }
+            }
+            stack.push((i1, epos, depth + 1));
+            stack[num_pushed..].reverse();
+        }
 
+        let num_elems = usize::try_from(fixed.num_nodes).unwrap();
+        let mut heap_bytes = self.mapper.heap_bytes() + num_elems * mem::size_of::<Node>();
+        if self.suffixes.is_some() {
+            let code_size = usize::from(utils::pack_size(self.mapper.alphabet_size() - 1));
+            let max_value = self.records.iter().map(|r| r.value).max().unwrap();
+            let value_size = usize::from(utils::pack_size(max_value));
+            for (spos, depth) in leaves {
+                let key = &self.records[spos].key;
+                // The leaf indicated with END_CODE holds its value instead of a tail.
+                if key[depth - 1] != END_MARKER {
+                    heap_bytes +=
+                        1 + strip_end_marker(&key[depth..]).len() * code_size + value_size;
+                }
+            }
+        } else if self.keep_keys {
+            let records = self
+                .records
+                .iter()
+                .map(|r| (strip_end_marker(&r.key), r.value));
+            heap_bytes += KeyStore::new(records)?.heap_bytes();
+        }
+        Ok(SizeEstimate {
+            num_elems,
+            num_vacants: num_elems - num_used,
+            heap_bytes,
+        })
+    }
+
+    /// Calls `callback` with the vacant ratio of the built double array
+    /// if it exceeds `threshold`.
+    ///
+    /// The ratio is the number of vacant elements divided by `num_elems()` of the resulting trie.
+    /// A high ratio signals a poor packing, e.g., caused by an adversarial key set.
+    /// Call it between a build method and a release method;
+    /// it only observes the built double array and does not change it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Builder;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let mut warned = false;
+    /// let trie = Builder::new()
+    ///     .build_from_keys(&keys)
+    ///     .unwrap()
+    ///     .warn_on_vacant_ratio(0.9, |_| warned = true)
+    ///     .release_trie()
+    ///     .unwrap();
+    ///
+    /// assert!(!warned);
+    /// ```
+    pub fn warn_on_vacant_ratio<F>(self, threshold: f64, callback: F) -> Self
+    where
+        F: FnOnce(f64),
+    {
+        if !self.nodes.is_empty() {
+            let num_vacants = self.nodes.iter().filter(|nd| nd.is_vacant()).count();
+            let ratio = num_vacants as f64 / self.nodes.len() as f64;
+            if threshold < ratio {
+                callback(ratio);
+            }
+        }
+        self
+    }
+
+    /// Returns the options to be kept in the built trie.
+    const fn options(&self) -> BuildOptions {
+        BuildOptions {
+            identity_mapping: self.identity_mapping,
+            code_order: self.code_order,
+            min_prefix_len: self.min_prefix_len,
+            keep_keys: self.keep_keys,
+            max_key_chars: self.max_key_chars,
+        }
+    }
+
+    /// Makes a [`StreamingBuilder`] with the configuration.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Builder;
+    ///
+    /// let mut builder = Builder::new().streaming();
+    /// builder.push("世界", 0).unwrap();
+    /// builder.push("世界中", 1).unwrap();
+    /// builder.push("国民", 2).unwrap();
+    /// let trie = builder.finish().unwrap();
+    ///
+    /// assert_eq!(trie.exact_match("世界中".chars()), Some(1));
+    /// ```
+    #[allow(clippy::missing_const_for_fn)]
+    pub fn streaming(self) -> StreamingBuilder {
+        StreamingBuilder { builder: self }
+    }
+
+    fn build_from_sorted_records(mut self) -> Result<Self> {
+        self.prepare_records()?;
+        self.init_array();
+        self.arrange_nodes()?;
+        self.finish();
+
+        Ok(self)
+    }
+
+    /// Checks the sorted records and makes the code mapper for them.
+    fn prepare_records(&mut self) -> Result<()> {
         for &Record { key: _, value } in &self.records {
             if MAX_VALUE < value {
                 return Err(CrawdadError::scale("input value", MAX_VALUE));
             }
         }
 
-        self.mapper = CodeMapper::new(&make_freqs(&self.records)?);
-        assert_eq!(self.mapper.get(END_MARKER).unwrap(), END_CODE);
+        let freqs = make_freqs(&self.records)?;
+        let mapper = self
+            .identity_mapping
+            .then(|| CodeMapper::ascii_identity(&freqs));
+        self.mapper = mapper.flatten().unwrap_or_else(|| match self.code_order {
+            CodeOrder::ByFrequency => CodeMapper::new(&freqs),
+            CodeOrder::ByCodepoint => CodeMapper::by_codepoint(&freqs),
+        });
 
         make_prefix_free(&mut self.records)?;
 
         self.block_len = self.mapper.alphabet_size().next_power_of_two().max(2);
-        self.init_array();
-        self.arrange_nodes(0, self.records.len(), 0, 0)?;
-        self.finish();
-
-        Ok(self)
+        Ok(())
     }
 
+    /// Releases the built [`Trie`].
+    ///
+    /// # Errors
+    ///
+    /// [`CrawdadError`] will be returned when
+    ///
+    /// - the minimal-prefix form is enabled, or
+    /// - the total length of keys to be kept exceeds the expected one.
     #[allow(clippy::missing_const_for_fn)]
     pub fn release_trie(self) -> Result<Trie> {
         if self.suffixes.is_some() {
             Err(CrawdadError::setup("minimal_prefix must be disabled."))
         } else {
-            let Self { nodes, mapper, .. } = self;
-            Ok(Trie { nodes, mapper })
+            let keys = if self.keep_keys {
+                let records = self
+                    .records
+                    .iter()
+                    .map(|r| (strip_end_marker(&r.key), r.value));
+                Some(KeyStore::new(records)?)
+            } else {
+                None
+            };
+            let options = self.options();
+            let Self {
+                nodes,
+                mapper,
+                payloads,
+                ..
+            } = self;
+            Ok(Trie {
+                mapper,
+                nodes,
+                keys,
+                payloads,
+                options,
+            })
         }
     }
 
+    /// Releases the built [`MpTrie`].
+    ///
+    /// # Errors
+    ///
+    /// [`CrawdadError`] will be returned when
+    ///
+    /// - the minimal-prefix form is disabled, or
+    /// - the scale of the resulting trie exceeds the expected one.
     pub fn release_mptrie(self) -> Result<MpTrie> {
+        let options = self.options();
         let Self {
             mapper,
             mut nodes,
@@ -183,9 +764,59 @@ impl Builder {
             tails,
             code_size,
             value_size,
+            options,
         })
     }
 
+    /// Builds a [`Trie`] from input keys and writes its serialized bytes into a writer.
+    ///
+    /// The bytes are the same as [`Trie::serialize_to_vec()`] and can be loaded with
+    /// [`Trie::deserialize_from_slice()`]. They are written by [`Trie::write_serialized()`],
+    /// so the whole bytes are never held together with the trie.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error will be returned when
+    ///
+    /// - the construction fails (see [`Trie::from_keys()`] and [`Builder::release_trie()`];
+    ///   the error kind is [`std::io::ErrorKind::InvalidInput`]), or
+    /// - the writer fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::{Builder, Trie};
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let mut buf = vec![];
+    /// Builder::new().build_serialized(&keys, &mut buf).unwrap();
+    ///
+    /// let (trie, _) = Trie::deserialize_from_slice(&buf);
+    /// assert_eq!(trie.exact_match("世界中".chars()), Some(1));
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn build_serialized<I, K, W>(self, keys: I, w: &mut W) -> std::io::Result<()>
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<str>,
+        W: std::io::Write,
+    {
+        let trie = self
+            .build_from_keys(keys)
+            .and_then(Self::release_trie)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+        trie.write_serialized(w)
+    }
+
+    #[inline(always)]
+    fn get_code(&self, c: char) -> u32 {
+        if c == END_MARKER {
+            END_CODE
+        } else {
+            self.mapper.get(c).unwrap()
+        }
+    }
+
     #[inline(always)]
     fn num_nodes(&self) -> u32 {
         self.nodes.len().try_into().unwrap()
@@ -213,20 +844,34 @@ impl Builder {
         self.fix_node(0);
     }
 
-    fn arrange_nodes(
+    /// Arranges nodes in the depth-first order from the root.
+    ///
+    /// An explicit stack is used instead of recursion so that deep keys do not overflow
+    /// the call stack.
+    fn arrange_nodes(&mut self) -> Result<()> {
+        let mut stack = vec![(0, self.records.len(), 0, 0)];
+        while let Some((spos, epos, depth, node_idx)) = stack.pop() {
+            self.arrange_node(spos, epos, depth, node_idx, &mut stack)?;
+        }
+        Ok(())
+    }
+
+    /// Arranges the children of a node for records in `spos..epos`,
+    /// pushing them to `stack` so that they are popped in the order of labels.
+    fn arrange_node(
         &mut self,
         spos: usize,
         epos: usize,
         depth: usize,
         node_idx: u32,
+        stack: &mut Vec<(usize, usize, usize, u32)>,
     ) -> Result<()> {
         debug_assert!(self.is_fixed(node_idx));
 
-        if let Some(suffixes) = self.suffixes.as_mut() {
-            if spos + 1 == epos {
-                // It has been checked in build_from_records().
-                debug_assert_eq!(self.records[spos].value & !OFFSET_MASK, 0);
-
+        if self.is_leaf_range(spos, epos, depth) {
+            // It has been checked in build_from_records().
+            debug_assert_eq!(self.records[spos].value & !OFFSET_MASK, 0);
+            if let Some(suffixes) = self.suffixes.as_mut() {
                 let suffix_idx = if suffixes.len() <= usize::try_from(OFFSET_MASK).unwrap() {
                     u32::try_from(suffixes.len()).unwrap()
                 } else {
@@ -237,35 +882,46 @@ impl Builder {
                     key: pop_end_marker(&self.records[spos].key[depth..]),
                     value: self.records[spos].value,
                 });
-                return Ok(());
+            } else {
+                // Sets IsLeaf = True
+                self.node_mut(node_idx).base = self.records[spos].value | !OFFSET_MASK;
+                // Note: HasLeaf must not be set here and should be set in finish()
+                // because MSB of check is used to indicate vacant element.
             }
-        } else if self.records[spos].key.len() == depth {
-            debug_assert_eq!(spos + 1, epos);
-            // It has been checked in build_from_records().
-            debug_assert_eq!(self.records[spos].value & !OFFSET_MASK, 0);
-            // Sets IsLeaf = True
-            self.node_mut(node_idx).base = self.records[spos].value | !OFFSET_MASK;
-            // Note: HasLeaf must not be set here and should be set in finish()
-            // because MSB of check is used to indicate vacant element.
             return Ok(());
         }
 
         self.fetch_labels(spos, epos, depth);
         let base = self.define_nodes(node_idx)?;
 
+        let num_pushed = stack.len();
         let mut i1 = spos;
         let mut c1 = self.records[i1].key[depth];
         for i2 in spos + 1..epos {
             let c2 = self.records[i2].key[depth];
             if c1 != c2 {
-                let child_idx = base ^ self.mapper.get(c1).unwrap();
-                self.arrange_nodes(i1, i2, depth + 1, child_idx)?;
+                let child_idx = base ^ self.get_code(c1);
+                stack.push((i1, i2, depth + 1, child_idx));
                 i1 = i2;
                 c1 = c2;
             }
         }
-        let child_idx = base ^ self.mapper.get(c1).unwrap();
-        self.arrange_nodes(i1, epos, depth + 1, child_idx)
+        let child_idx = base ^ self.get_code(c1);
+        stack.push((i1, epos, depth + 1, child_idx));
+        stack[num_pushed..].reverse();
+        Ok(())
+    }
+
+    /// Checks if the node for records in `spos..epos` at `depth` is a leaf.
+    fn is_leaf_range(&self, spos: usize, epos: usize, depth: usize) -> bool {
+        let key_len = self.records[spos].key.len();
+        if self.suffixes.is_some() {
+            // The root must not be a leaf even for a single key, since it has no parent.
+            spos + 1 == epos && depth != 0 && (self.min_prefix_len <= depth || key_len == depth)
+        } else {
+            debug_assert!(key_len != depth || spos + 1 == epos);
+            key_len == depth
+        }
     }
 
     fn finish(&mut self) {
@@ -303,11 +959,11 @@ impl Builder {
         for i in spos + 1..epos {
             let c2 = self.records[i].key[depth];
             if c1 != c2 {
-                self.labels.push(self.mapper.get(c1).unwrap());
+                self.labels.push(self.get_code(c1));
                 c1 = c2;
             }
         }
-        self.labels.push(self.mapper.get(c1).unwrap());
+        self.labels.push(self.get_code(c1));
     }
 
     fn define_nodes(&mut self, node_idx: u32) -> Result<u32> {
@@ -381,8 +1037,10 @@ impl Builder {
         let old_len = self.num_nodes();
         let new_len = old_len + self.block_len;
 
-        if OFFSET_MASK < new_len {
-            return Err(CrawdadError::scale("num_nodes", OFFSET_MASK));
+        // Node indices must fit in OFFSET_MASK bits so as not to alias INVALID_IDX
+        // or flags in the most significant bits.
+        if self.max_num_nodes < new_len {
+            return Err(CrawdadError::too_many_nodes(new_len, self.max_num_nodes));
         }
 
         let num_blocks = old_len / self.block_len;
@@ -476,16 +1134,311 @@ impl Builder {
     }
 }
 
+/// Order of characters in which codes are assigned, given to [`Builder::code_order()`].
+///
+/// In any order, [`END_MARKER`] is assigned code `0`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CodeOrder {
+    /// Assigns smaller codes to more frequent characters in keys,
+    /// where ties are broken by code points. This is the default,
+    /// which often makes the trie compact.
+    ByFrequency,
+
+    /// Assigns codes in the order of code points, independently of the frequencies.
+    /// The resulting codes are easy to compare between tries built from different keys.
+    ByCodepoint,
+}
+
+impl Default for CodeOrder {
+    fn default() -> Self {
+        Self::ByFrequency
+    }
+}
+
+/// Options of [`Builder`] kept in a built trie to rebuild it with the same ones,
+/// e.g., in [`Extend`].
+///
+/// [`Builder::value_offset()`] is not kept because it applies only to the values of
+/// input keys, not to those of input records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct BuildOptions {
+    identity_mapping: bool,
+    code_order: CodeOrder,
+    min_prefix_len: usize,
+    keep_keys: bool,
+    max_key_chars: usize,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Builder::new().options()
+    }
+}
+
+impl BuildOptions {
+    /// Makes a builder with the options,
+    /// enabling the minimal-prefix form if `minimal_prefix` is true.
+    pub fn to_builder(self, minimal_prefix: bool) -> Builder {
+        let builder = Builder {
+            identity_mapping: self.identity_mapping,
+            code_order: self.code_order,
+            keep_keys: self.keep_keys,
+            max_key_chars: self.max_key_chars,
+            ..Builder::new()
+        };
+        if minimal_prefix {
+            builder.minimal_prefix_len(self.min_prefix_len)
+        } else {
+            builder
+        }
+    }
+}
+
+/// Size of a trie estimated by [`Builder::estimate()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizeEstimate {
+    /// Number of reserved elements, the same as `num_elems()` of the resulting trie.
+    pub num_elems: usize,
+
+    /// Number of vacant elements, the same as `num_vacants()` of the resulting trie.
+    pub num_vacants: usize,
+
+    /// Total amount of heap used, the same as `heap_bytes()` of the resulting trie.
+    pub heap_bytes: usize,
+}
+
+impl SizeEstimate {
+    /// Returns the ratio of vacant elements.
+    pub fn vacant_ratio(&self) -> f64 {
+        self.num_vacants as f64 / self.num_elems as f64
+    }
+}
+
+/// Bitmap of fixed elements to simulate the placement of nodes in [`Builder::estimate()`].
+///
+/// Unfixed elements are searched in ascending order of indices,
+/// the same as in the doubly linked list of [`Builder`].
+struct FixedMap {
+    bits: Vec<u64>,
+    num_nodes: u32,
+    // Smallest index of unfixed elements, or num_nodes if all the elements are fixed.
+    head_idx: u32,
+}
+
+impl FixedMap {
+    fn new(block_len: u32) -> Self {
+        let mut fixed = Self {
+            bits: vec![],
+            num_nodes: 0,
+            head_idx: 0,
+        };
+        fixed.enlarge(block_len, u32::MAX);
+        fixed.fix(0);
+        fixed
+    }
+
+    #[inline(always)]
+    fn is_fixed(&self, i: u32) -> bool {
+        let i = usize::try_from(i).unwrap();
+        self.bits[i / 64] >> (i % 64) & 1 != 0
+    }
+
+    #[inline(always)]
+    fn fix(&mut self, i: u32) {
+        debug_assert!(!self.is_fixed(i));
+        let j = usize::try_from(i).unwrap();
+        self.bits[j / 64] |= 1 << (j % 64);
+        if self.head_idx == i {
+            self.head_idx = self.next_unfixed(i);
+        }
+    }
+
+    /// Returns the smallest index of unfixed elements greater than `i`, or `num_nodes` if none.
+    #[inline(always)]
+    fn next_unfixed(&self, i: u32) -> u32 {
+        let i = usize::try_from(i).unwrap() + 1;
+        let mut w = i / 64;
+        let mut word = !self.bits.get(w).copied().unwrap_or(u64::MAX) & (u64::MAX << (i % 64));
+        while word == 0 {
+            w += 1;
+            match self.bits.get(w) {
+                Some(&bits) => word = !bits,
+                None => return self.num_nodes,
+            }
+        }
+        let found = u32::try_from(w * 64).unwrap() + word.trailing_zeros();
+        found.min(self.num_nodes)
+    }
+
+    /// Works as `Builder::find_base()`.
+    fn find_base(&self, labels: &[u32]) -> u32 {
+        let mut node_idx = self.head_idx;
+        while node_idx < self.num_nodes {
+            let base = node_idx ^ labels[0];
+            if labels.iter().all(|&label| !self.is_fixed(base ^ label)) {
+                return base;
+            }
+            node_idx = self.next_unfixed(node_idx);
+        }
+        self.num_nodes ^ labels[0]
+    }
+
+    /// Works as `Builder::enlarge()` without the check of the number of nodes.
+    fn enlarge(&mut self, block_len: u32, num_free_blocks: u32) {
+        let num_blocks = self.num_nodes / block_len;
+        if num_free_blocks <= num_blocks {
+            // Fixes the unfixed elements in the block to close it.
+            let end_idx = (num_blocks - num_free_blocks + 1) * block_len;
+            while self.head_idx < end_idx {
+                self.fix(self.head_idx);
+            }
+        }
+        // If all the elements are fixed, head_idx points to the first new element.
+        self.num_nodes += block_len;
+        self.bits
+            .resize((usize::try_from(self.num_nodes).unwrap() + 63) / 64, 0);
+    }
+}
+
+/// Builder that constructs a trie from records pushed one at a time,
+/// created by [`Builder::streaming()`].
+///
+/// Records must be pushed in strictly ascending order of keys, i.e., the order of [`Ord`] of
+/// [`str`], which is the same as the order of Unicode scalar values used by the builder.
+/// They are buffered, and the double array is constructed in [`StreamingBuilder::finish()`].
+pub struct StreamingBuilder {
+    builder: Builder,
+}
+
+impl StreamingBuilder {
+    /// Pushes a record.
+    ///
+    /// # Arguments
+    ///
+    /// - `key`: String key.
+    /// - `value`: Value associated with `key`.
+    ///
+    /// # Errors
+    ///
+    /// [`CrawdadError`] will be returned when
+    ///
+    /// - `key` is empty,
+    /// - `key` is longer than [`Builder::max_key_chars()`],
+    /// - `key` is not greater than the previously pushed key
+    ///   ([`CrawdadError::UnsortedKey`] reporting both the keys), or
+    /// - `value` exceeds the expected one.
+    ///
+    /// The rejected record is not pushed, and the builder can still be used.
+    pub fn push(&mut self, key: &str, value: u32) -> Result<()> {
+        if key.is_empty() {
+            return Err(CrawdadError::input(
+                "records must not contain an empty key.",
+            ));
+        }
+        if MAX_VALUE < value {
+            return Err(CrawdadError::scale("input value", MAX_VALUE));
+        }
+        let record = Record::new(key, value);
+        check_key_chars(self.len(), &record, self.builder.max_key_chars)?;
+        if let Some(last) = self.builder.records.last() {
+            if last.key >= record.key {
+                let prev_key: String = last.key.iter().collect();
+                return Err(CrawdadError::unsorted_key(self.len(), key, &prev_key));
+            }
+        }
+        self.builder.records.push(record);
+        Ok(())
+    }
+
+    /// Returns the number of pushed records.
+    pub fn len(&self) -> usize {
+        self.builder.records.len()
+    }
+
+    /// Checks if no record is pushed.
+    pub fn is_empty(&self) -> bool {
+        self.builder.records.is_empty()
+    }
+
+    /// Builds a [`Trie`] from the pushed records.
+    ///
+    /// # Errors
+    ///
+    /// See [`Trie::from_records()`] and [`Builder::release_trie()`].
+    pub fn finish(self) -> Result<Trie> {
+        self.builder.build_from_sorted_records()?.release_trie()
+    }
+
+    /// Builds an [`MpTrie`] from the pushed records.
+    ///
+    /// # Errors
+    ///
+    /// See [`MpTrie::from_records()`] and [`Builder::release_mptrie()`].
+    pub fn finish_mptrie(self) -> Result<MpTrie> {
+        self.builder.build_from_sorted_records()?.release_mptrie()
+    }
+}
+
+/// Sorts and deduplicates keys so that they can be given to [`Trie::from_keys()`].
+///
+/// Keys are sorted by the [`Ord`] of [`str`], i.e., in the byte order of UTF-8,
+/// which is the same as the order of Unicode scalar values used by the builder.
+/// Since [`Trie::from_keys()`] associates each key with its index,
+/// the value of a key becomes its rank in the lexicographical order.
+///
+/// # Examples
+///
+/// ```
+/// use crawdad::Trie;
+///
+/// let keys = crawdad::prepare_keys(["国民", "世界中", "世界", "国民"]);
+/// assert_eq!(keys, vec!["世界", "世界中", "国民"]);
+///
+/// let trie = Trie::from_keys(&keys).unwrap();
+/// assert_eq!(trie.exact_match("国民".chars()), Some(2));
+/// ```
+pub fn prepare_keys<I, K>(keys: I) -> Vec<String>
+where
+    I: IntoIterator<Item = K>,
+    K: AsRef<str>,
+{
+    let mut keys: Vec<_> = keys.into_iter().map(|k| k.as_ref().to_string()).collect();
+    keys.sort_unstable();
+    keys.dedup();
+    keys
+}
+
+/// Checks that the `index`-th record has a key no longer than `limit` characters.
+#[inline(always)]
+fn check_key_chars(index: usize, record: &Record, limit: usize) -> Result<()> {
+    if limit < record.key.len() {
+        return Err(CrawdadError::key_too_long(index, record.key.len(), limit));
+    }
+    Ok(())
+}
+
+/// Converts the index of a key into its value shifted by `offset`.
+///
+/// Values not fitting in u32 are saturated to be rejected as too large values
+/// in `build_from_sorted_records()`, instead of being truncated.
+#[inline(always)]
+fn index_to_value(i: usize, offset: u32) -> u32 {
+    u32::try_from(i)
+        .ok()
+        .and_then(|v| v.checked_add(offset))
+        .unwrap_or(u32::MAX)
+}
+
 fn make_freqs(records: &[Record]) -> Result<Vec<u32>> {
     let end_marker = usize::try_from(u32::from(END_MARKER)).unwrap();
-    let mut freqs = vec![0; end_marker + 1];
+    let mut freqs = vec![0u32; end_marker + 1];
     for rec in records {
         for &c in &rec.key {
             let c = usize::try_from(u32::from(c)).unwrap();
             if freqs.len() <= c {
                 freqs.resize(c + 1, 0);
             }
-            freqs[c] += 1;
+            freqs[c] = freqs[c].saturating_add(1);
         }
     }
     if let Some(&freq) = freqs.get(end_marker) {
@@ -493,6 +1446,8 @@ fn make_freqs(records: &[Record]) -> Result<Vec<u32>> {
             return Err(CrawdadError::input("END_MARKER must not be contained."));
         }
     }
+    // Keeps the frequency of END_MARKER the highest to assign it END_CODE.
+    freqs.iter_mut().for_each(|f| *f = (*f).min(u32::MAX - 1));
     freqs[end_marker] = u32::MAX;
     Ok(freqs)
 }
@@ -526,9 +1481,494 @@ fn make_prefix_free(records: &mut [Record]) -> Result<()> {
     Ok(())
 }
 
-fn pop_end_marker(x: &[char]) -> Vec<char> {
+#[allow(clippy::missing_const_for_fn)]
+fn strip_end_marker(x: &[char]) -> &[char] {
     match x.split_last() {
-        Some((&END_MARKER, elems)) => elems.to_vec(),
-        _ => x.to_vec(),
+        Some((&END_MARKER, elems)) => elems,
+        _ => x,
+    }
+}
+
+fn pop_end_marker(x: &[char]) -> Vec<char> {
+    strip_end_marker(x).to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warn_on_vacant_ratio() {
+        let mut ratios = vec![];
+
+        // A long key with distinct characters leaves about half of the blocks vacant.
+        let sparse: alloc::string::String = (0..65)
+            .map(|i| char::from_u32(0x4e00 + i).unwrap())
+            .collect();
+        let trie = Builder::new()
+            .build_from_keys([&sparse])
+            .unwrap()
+            .warn_on_vacant_ratio(0.4, |ratio| ratios.push(ratio))
+            .release_trie()
+            .unwrap();
+        assert_eq!(ratios.len(), 1);
+        assert!(0.4 < ratios[0]);
+        assert_eq!(
+            ratios[0],
+            trie.num_vacants() as f64 / trie.num_elems() as f64
+        );
+        let other = Trie::from_keys([&sparse]).unwrap();
+        assert_eq!(trie.nodes, other.nodes);
+
+        let dense = ["a", "b", "c"];
+        let trie = Builder::new()
+            .build_from_keys(dense)
+            .unwrap()
+            .warn_on_vacant_ratio(0.4, |ratio| ratios.push(ratio))
+            .release_trie()
+            .unwrap();
+        assert_eq!(ratios.len(), 1);
+        assert_eq!(trie.num_vacants(), 0);
+
+        // Nothing is built yet.
+        Builder::new().warn_on_vacant_ratio(0.0, |ratio| ratios.push(ratio));
+        assert_eq!(ratios.len(), 1);
+    }
+
+    #[test]
+    fn test_prepare_keys() {
+        let keys = [
+            "世界",
+            "a",
+            "世界中",
+            "🦞",
+            "\u{ff}",
+            "世",
+            "a",
+            "ab",
+            "\u{10ffff}",
+            "世界",
+        ];
+        let prepared = prepare_keys(keys);
+        assert_eq!(
+            prepared,
+            vec![
+                "a",
+                "ab",
+                "\u{ff}",
+                "世",
+                "世界",
+                "世界中",
+                "🦞",
+                "\u{10ffff}"
+            ]
+        );
+
+        // The order is the same as that of characters used by the builder.
+        let mut chars: Vec<Vec<char>> = prepared.iter().map(|k| k.chars().collect()).collect();
+        chars.sort_unstable();
+        let sorted: Vec<String> = chars.iter().map(|k| k.iter().collect()).collect();
+        assert_eq!(prepared, sorted);
+
+        let trie = Trie::from_keys(&prepared).unwrap();
+        let expected: Vec<_> = prepared
+            .iter()
+            .enumerate()
+            .map(|(i, k)| (k.clone(), u32::try_from(i).unwrap()))
+            .collect();
+        assert_eq!(trie.iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_multibyte_keys_in_str_order() {
+        // The byte order of UTF-8 differs from the order of UTF-16 code units
+        // for characters beyond the BMP, e.g., '🦞' < '\u{ff61}' in UTF-16.
+        let mut keys = vec![
+            "\u{ff61}",
+            "🦞",
+            "é",
+            "e",
+            "世界",
+            "世",
+            "\u{7f}",
+            "ab\u{10ffff}",
+        ];
+        keys.sort_unstable();
+
+        let trie = Trie::from_keys(&keys).unwrap();
+        let mut streaming = Builder::new().streaming();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(
+                trie.exact_match(key.chars()),
+                Some(u32::try_from(i).unwrap())
+            );
+            streaming.push(key, u32::try_from(i).unwrap()).unwrap();
+        }
+        let other = streaming.finish().unwrap();
+        assert_eq!(trie.nodes, other.nodes);
+        assert_eq!(
+            trie.iter().map(|(k, _)| k).collect::<Vec<_>>(),
+            keys.iter().map(|k| k.to_string()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_reuse_buffers() {
+        let keys: Vec<_> = (0..100).map(|i| format!("{}世界", i * 7)).collect();
+        let mut trie = Trie::from_keys(&keys).unwrap();
+        let expected = trie.serialize_to_vec();
+        trie.clear();
+        let ptr = trie.nodes.as_ptr();
+        let capacity = trie.nodes.capacity();
+
+        let trie = Builder::new()
+            .reuse_buffers(trie)
+            .build_from_keys(&keys)
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        assert_eq!(trie.nodes.as_ptr(), ptr);
+        assert_eq!(trie.nodes.capacity(), capacity);
+        assert_eq!(trie.serialize_to_vec(), expected);
+    }
+
+    #[test]
+    fn test_value_offset() {
+        let keys = vec!["世界中", "国民", "世界", "世論調査"];
+        for offset in [0, 1, 100] {
+            let trie = Builder::new()
+                .value_offset(offset)
+                .build_from_keys(&keys)
+                .unwrap()
+                .release_trie()
+                .unwrap();
+            for (i, key) in keys.iter().enumerate() {
+                let value = offset + u32::try_from(i).unwrap();
+                assert_eq!(trie.exact_match(key.chars()), Some(value));
+            }
+        }
+
+        // The largest value must fit.
+        let build = |offset| Builder::new().value_offset(offset).build_from_keys(&keys);
+        assert!(build(MAX_VALUE - 3).is_ok());
+        assert!(matches!(build(MAX_VALUE - 2), Err(CrawdadError::Scale(_))));
+        assert!(matches!(build(u32::MAX), Err(CrawdadError::Scale(_))));
+
+        // Explicit values are not shifted.
+        let trie = Builder::new()
+            .value_offset(1)
+            .build_from_records([("世界", 0)])
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        assert_eq!(trie.exact_match("世界".chars()), Some(0));
+    }
+
+    #[test]
+    fn test_code_order() {
+        let keys = vec!["世界中", "世論調査", "国民", "世界", "統計調査", "扉"];
+        let build = |order, keys: &[&str]| {
+            Builder::new()
+                .code_order(order)
+                .build_from_keys(keys)
+                .unwrap()
+                .release_trie()
+                .unwrap()
+        };
+        let mut rev_keys = keys.clone();
+        rev_keys.reverse();
+
+        let trie = build(CodeOrder::ByFrequency, &keys);
+        assert_eq!(
+            trie.serialize_to_vec(),
+            build(CodeOrder::default(), &keys).serialize_to_vec()
+        );
+        // The codes only depend on the set of keys.
+        assert_eq!(trie.mapper, build(CodeOrder::ByFrequency, &rev_keys).mapper);
+        assert_eq!(trie.mapper.get('世'), Some(1));
+        // Ties of frequency two are broken by code points.
+        assert_eq!(trie.mapper.get('査'), Some(2));
+        assert_eq!(trie.mapper.get('界'), Some(3));
+        assert_eq!(trie.mapper.get('調'), Some(4));
+
+        let trie = build(CodeOrder::ByCodepoint, &keys);
+        assert_eq!(trie.mapper, build(CodeOrder::ByCodepoint, &rev_keys).mapper);
+        let mut chars: Vec<_> = keys.iter().flat_map(|k| k.chars()).collect();
+        chars.sort_unstable();
+        chars.dedup();
+        for (i, &c) in chars.iter().enumerate() {
+            assert_eq!(trie.mapper.get(c), Some(u32::try_from(i + 1).unwrap()));
+        }
+        assert_eq!(
+            trie.mapper.alphabet_size(),
+            u32::try_from(chars.len() + 1).unwrap()
+        );
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(
+                trie.exact_match(key.chars()),
+                Some(u32::try_from(i).unwrap())
+            );
+        }
+        assert!(trie.validate().is_ok());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_build_serialized() {
+        let keys: Vec<_> = (0..1000).map(|i| format!("{}世界", i * 7)).collect();
+        let mut buf = vec![];
+        Builder::new().build_serialized(&keys, &mut buf).unwrap();
+        assert_eq!(buf, Trie::from_keys(&keys).unwrap().serialize_to_vec());
+
+        let (trie, rest) = Trie::try_deserialize_from_slice(&buf).unwrap();
+        assert!(rest.is_empty());
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(
+                trie.exact_match(key.chars()),
+                Some(u32::try_from(i).unwrap())
+            );
+        }
+
+        let e = Builder::new()
+            .build_serialized(["世界", ""], &mut vec![])
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        let e = Builder::new()
+            .minimal_prefix()
+            .build_serialized(["世界"], &mut vec![])
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_build_from_payloads() {
+        let records = vec![
+            ("統計調査", "とうけいちょうさ"),
+            ("世界中", "せかいじゅう"),
+            ("世界", "せかい"),
+            ("世論調査", "よろんちょうさ"),
+            ("国", ""),
+        ];
+        let trie = Builder::new()
+            .value_offset(10)
+            .build_from_payloads(records.clone())
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        for (i, &(key, reading)) in records.iter().enumerate() {
+            assert_eq!(
+                trie.exact_match(key.chars()),
+                Some(u32::try_from(i).unwrap())
+            );
+            assert_eq!(trie.payload(key.chars()), Some(reading.as_bytes()));
+        }
+        assert_eq!(trie.payload("世論".chars()), None);
+        assert!(
+            trie.heap_bytes()
+                > Trie::from_keys(records.iter().map(|r| r.0))
+                    .unwrap()
+                    .heap_bytes()
+        );
+
+        // Payloads are dropped by a build without them.
+        let builder = Builder::new()
+            .build_from_payloads([("世界", [1u8, 2])])
+            .unwrap()
+            .build_from_keys(["世界"])
+            .unwrap();
+        assert_eq!(
+            builder.release_trie().unwrap().payload("世界".chars()),
+            None
+        );
+
+        let trie = Trie::from_keys(["世界"]).unwrap();
+        assert_eq!(trie.payload("世界".chars()), None);
+        assert!(Builder::new()
+            .build_from_payloads([("世界", "せかい"), ("", "")])
+            .is_err());
+    }
+
+    #[test]
+    fn test_max_key_chars() {
+        let keys = ["世界", "世界中で", "国民", "世論調査会"];
+        let builder = Builder::new().max_key_chars(3);
+        match builder.clone().build_from_keys(keys) {
+            Err(CrawdadError::KeyTooLong(e)) => {
+                assert_eq!((e.index(), e.len(), e.limit()), (1, 4, 3));
+            }
+            _ => panic!(),
+        }
+        assert!(builder.clone().build_from_keys(&keys[..1]).is_ok());
+        assert!(Builder::new()
+            .max_key_chars(5)
+            .build_from_keys(keys)
+            .is_ok());
+
+        let mut streaming = builder.streaming();
+        streaming.push("世界", 0).unwrap();
+        match streaming.push("世界中で", 1) {
+            Err(CrawdadError::KeyTooLong(e)) => {
+                assert_eq!((e.index(), e.len(), e.limit()), (1, 4, 3));
+            }
+            _ => panic!(),
+        }
+        streaming.push("国民", 1).unwrap();
+        assert_eq!(streaming.len(), 2);
+    }
+
+    #[test]
+    fn test_index_to_value() {
+        assert_eq!(index_to_value(0, 0), 0);
+        let max_index = usize::try_from(MAX_VALUE).unwrap();
+        assert_eq!(index_to_value(max_index, 0), MAX_VALUE);
+        assert_eq!(index_to_value(max_index + 1, 0), MAX_VALUE + 1);
+        assert_eq!(index_to_value(usize::MAX, 0), u32::MAX);
+        assert_eq!(index_to_value(2, 3), 5);
+        assert_eq!(index_to_value(1, u32::MAX), u32::MAX);
+
+        // Values of out-of-range indices are rejected, not truncated.
+        for i in [max_index + 1, usize::MAX] {
+            let result = Builder::new().build_from_records([("世界", index_to_value(i, 0))]);
+            assert!(matches!(result, Err(CrawdadError::Scale(_))));
+        }
+    }
+
+    #[test]
+    fn test_errors_through_chain() {
+        let invalid_keys: [&[&str]; 3] = [&[], &["世界", ""], &["世界", "世界"]];
+        for keys in invalid_keys {
+            let result = Builder::new()
+                .build_from_keys(keys)
+                .and_then(Builder::release_trie);
+            assert!(matches!(result, Err(CrawdadError::Input(_))));
+        }
+
+        let result = Builder::new()
+            .minimal_prefix()
+            .build_from_keys(["世界"])
+            .and_then(Builder::release_trie);
+        assert!(matches!(result, Err(CrawdadError::Setup(_))));
+
+        let result = Builder::new()
+            .build_from_records([("世界", u32::MAX)])
+            .and_then(Builder::release_trie);
+        assert!(matches!(result, Err(CrawdadError::Scale(_))));
+    }
+
+    #[test]
+    fn test_clone() {
+        let builder = Builder::new().minimal_prefix_len(1).identity_mapping();
+
+        let trie1 = builder
+            .clone()
+            .build_from_keys(["ab", "abc", "b"])
+            .unwrap()
+            .release_mptrie()
+            .unwrap();
+        let trie2 = builder
+            .build_from_keys(["xyz", "yz"])
+            .unwrap()
+            .release_mptrie()
+            .unwrap();
+
+        assert_eq!(trie1.exact_match("abc".chars()), Some(1));
+        assert_eq!(trie1.exact_match("xyz".chars()), None);
+        assert_eq!(trie2.exact_match("xyz".chars()), Some(0));
+        assert_eq!(trie2.exact_match("abc".chars()), None);
+        assert_eq!(trie1.mapper.alphabet_size(), 129);
+        assert_eq!(trie2.mapper.alphabet_size(), 129);
+    }
+
+    #[test]
+    fn test_estimate() {
+        let mut key_sets = vec![
+            (0..1000)
+                .map(|i| format!("{}世界", i * 7))
+                .collect::<Vec<_>>(),
+            (0..10000).map(|i| format!("{}", i)).collect(),
+        ];
+        // Long keys of distinct characters leave many blocks vacant.
+        key_sets.push(
+            (0..3)
+                .map(|i| {
+                    (0..65)
+                        .map(|j| char::from_u32(0x4e00 + i + j).unwrap())
+                        .collect()
+                })
+                .collect(),
+        );
+        let builders = [
+            Builder::new(),
+            Builder::new().keep_keys(),
+            Builder::new().identity_mapping(),
+            Builder::new().code_order(CodeOrder::ByCodepoint),
+        ];
+        for keys in &key_sets {
+            for builder in &builders {
+                let estimate = builder.clone().estimate(keys).unwrap();
+                let trie = builder
+                    .clone()
+                    .build_from_keys(keys)
+                    .unwrap()
+                    .release_trie()
+                    .unwrap();
+                assert_eq!(estimate.num_elems, trie.num_elems());
+                assert_eq!(estimate.num_vacants, trie.num_vacants());
+                assert_eq!(estimate.heap_bytes, trie.heap_bytes());
+            }
+            for min in 0..4 {
+                let builder = Builder::new().minimal_prefix_len(min);
+                let estimate = builder.clone().estimate(keys).unwrap();
+                let trie = builder
+                    .build_from_keys(keys)
+                    .unwrap()
+                    .release_mptrie()
+                    .unwrap();
+                assert_eq!(estimate.num_elems, trie.num_elems());
+                assert_eq!(estimate.num_vacants, trie.num_vacants());
+                assert_eq!(estimate.heap_bytes, trie.heap_bytes());
+            }
+        }
+
+        let builder = Builder {
+            max_num_nodes: 256,
+            ..Builder::new()
+        };
+        assert!(matches!(
+            builder.estimate(&key_sets[1]),
+            Err(CrawdadError::TooManyNodes(_))
+        ));
+        assert!(matches!(
+            Builder::new().estimate(["世界", ""]),
+            Err(CrawdadError::Input(_))
+        ));
+    }
+
+    #[test]
+    fn test_max_num_nodes() {
+        let keys: Vec<_> = (0..1000).map(|i| format!("{}", i)).collect();
+        let builder = Builder {
+            max_num_nodes: 256,
+            ..Builder::new()
+        };
+        match builder.build_from_keys(&keys) {
+            Err(CrawdadError::TooManyNodes(e)) => {
+                assert_eq!(e.limit(), 256);
+                assert!(256 < e.count());
+            }
+            _ => panic!("the number of nodes must be limited."),
+        }
+
+        let builder = Builder {
+            max_num_nodes: 4096,
+            ..Builder::new()
+        };
+        let trie = builder
+            .build_from_keys(&keys)
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        assert!(256 < trie.num_elems() && trie.num_elems() <= 4096);
+        assert_eq!(trie.exact_match("123".chars()), Some(123));
     }
 }
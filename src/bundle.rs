@@ -0,0 +1,317 @@
+//! Container of named tries serialized into a single artifact.
+use crate::errors::{CrawdadError, Result};
+use crate::{utils, Trie};
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Container of named [`Trie`]s serialized into a single artifact.
+///
+/// The serialized form starts with an index header of names and byte lengths,
+/// followed by the tries serialized by [`Trie::serialize_to_vec()`] in the order of the index.
+/// Payloads are serialized with the tries,
+/// but keys kept by [`Builder::keep_keys()`](crate::Builder::keep_keys) are not.
+///
+/// # Examples
+///
+/// ```
+/// use crawdad::{Trie, TrieBundle};
+///
+/// let mut bundle = TrieBundle::new();
+/// bundle.add("place", Trie::from_keys(["世界", "世界中"]).unwrap());
+/// bundle.add("people", Trie::from_keys(["国民"]).unwrap());
+///
+/// let bytes = bundle.serialize_to_vec();
+/// let (other, _) = TrieBundle::try_deserialize_from_slice(&bytes).unwrap();
+///
+/// assert_eq!(other.get("place").unwrap().exact_match("世界中".chars()), Some(1));
+/// assert_eq!(other.get("people").unwrap().exact_match("国民".chars()), Some(0));
+/// assert!(other.get("time").is_none());
+/// ```
+#[derive(Default)]
+pub struct TrieBundle {
+    entries: Vec<(String, Trie)>,
+}
+
+impl TrieBundle {
+    /// Creates an empty bundle.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a trie with a name, returning the trie previously added with the same name if exists.
+    ///
+    /// The new trie keeps the position of the replaced one in the index.
+    pub fn add(&mut self, name: &str, trie: Trie) -> Option<Trie> {
+        if let Some((_, old)) = self.entries.iter_mut().find(|(n, _)| n == name) {
+            return Some(core::mem::replace(old, trie));
+        }
+        self.entries.push((name.to_string(), trie));
+        None
+    }
+
+    /// Returns the trie with a name if exists.
+    pub fn get(&self, name: &str) -> Option<&Trie> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, trie)| trie)
+    }
+
+    /// Returns an iterator over names in the order of addition.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Returns the number of tries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Checks if the bundle has no trie.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Serializes the data structure into a [`Vec`].
+    pub fn serialize_to_vec(&self) -> Vec<u8> {
+        let mut dest = vec![];
+        dest.extend_from_slice(&u32::try_from(self.entries.len()).unwrap().to_le_bytes());
+        for (name, trie) in &self.entries {
+            dest.extend_from_slice(&u32::try_from(name.len()).unwrap().to_le_bytes());
+            dest.extend_from_slice(name.as_bytes());
+            dest.extend_from_slice(&u64::try_from(trie.io_bytes()).unwrap().to_le_bytes());
+        }
+        for (_, trie) in &self.entries {
+            dest.extend_from_slice(&trie.serialize_to_vec());
+        }
+        dest
+    }
+
+    /// Deserializes the data structure from a given byte slice
+    /// while checking that it is not broken.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - A source byte slice.
+    ///
+    /// # Returns
+    ///
+    /// A tuple of the data structure and the slice not used for the deserialization.
+    ///
+    /// # Errors
+    ///
+    /// [`CrawdadError::Corrupted`] will be returned when
+    ///
+    /// - `source` is too short,
+    /// - a name is not valid UTF-8 or is duplicated,
+    /// - a trie does not have the length recorded in the index, or
+    /// - a trie is broken (see [`Trie::try_deserialize_from_slice()`]).
+    pub fn try_deserialize_from_slice(mut source: &[u8]) -> Result<(Self, &[u8])> {
+        let len = utils::split_u32(&mut source)?;
+        let mut index = vec![];
+        for _ in 0..len {
+            let name_len = utils::split_u32(&mut source)?;
+            let name = utils::split_array(&mut source, name_len, 1)?;
+            let name = core::str::from_utf8(name)
+                .map_err(|_| CrawdadError::corrupted("name must be valid UTF-8."))?;
+            let trie_len = utils::split_bytes(&mut source, 8)?;
+            let trie_len = usize::try_from(u64::from_le_bytes(trie_len.try_into().unwrap()))
+                .map_err(|_| CrawdadError::corrupted("source is too short."))?;
+            index.push((name, trie_len));
+        }
+        let mut bundle = Self::new();
+        for (name, trie_len) in index {
+            let (trie, rest) =
+                Trie::try_deserialize_from_slice(utils::split_bytes(&mut source, trie_len)?)?;
+            if !rest.is_empty() {
+                return Err(CrawdadError::corrupted("trie length must match the index."));
+            }
+            if bundle.add(name, trie).is_some() {
+                return Err(CrawdadError::corrupted("names must be unique."));
+            }
+        }
+        Ok((bundle, source))
+    }
+
+    /// Writes the serialized data structure into a writer.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error will be returned when the writer fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::{Trie, TrieBundle};
+    ///
+    /// let mut bundle = TrieBundle::new();
+    /// bundle.add("place", Trie::from_keys(["世界", "世界中"]).unwrap());
+    ///
+    /// let mut buf = vec![];
+    /// bundle.write(&mut buf).unwrap();
+    ///
+    /// let other = TrieBundle::read(&mut buf.as_slice()).unwrap();
+    /// assert_eq!(other.get("place").unwrap().exact_match("世界".chars()), Some(0));
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn write<W>(&self, w: &mut W) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        w.write_all(&self.serialize_to_vec())
+    }
+
+    /// Reads the data structure from a reader until its end.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error will be returned when
+    ///
+    /// - the reader fails, or
+    /// - the read bytes are broken or have trailing bytes
+    ///   (the error kind is [`std::io::ErrorKind::InvalidData`]).
+    #[cfg(feature = "std")]
+    pub fn read<R>(r: &mut R) -> std::io::Result<Self>
+    where
+        R: std::io::Read,
+    {
+        let mut bytes = vec![];
+        r.read_to_end(&mut bytes)?;
+        let invalid_data = |msg| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+        match Self::try_deserialize_from_slice(&bytes) {
+            Ok((bundle, [])) => Ok(bundle),
+            Ok(_) => Err(invalid_data("trailing bytes after the bundle.".to_string())),
+            Err(e) => Err(invalid_data(e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_bundle() -> TrieBundle {
+        let mut bundle = TrieBundle::new();
+        assert!(bundle
+            .add("place", Trie::from_keys(["世界", "世界中"]).unwrap())
+            .is_none());
+        assert!(bundle
+            .add("people", Trie::from_keys(["国民", "市民"]).unwrap())
+            .is_none());
+        bundle
+    }
+
+    #[test]
+    fn test_add_and_get() {
+        let mut bundle = make_bundle();
+        assert_eq!(bundle.len(), 2);
+        assert_eq!(bundle.names().collect::<Vec<_>>(), vec!["place", "people"]);
+
+        let old = bundle.add("place", Trie::from_keys(["統計"]).unwrap());
+        assert_eq!(old.unwrap().exact_match("世界".chars()), Some(0));
+        assert_eq!(bundle.len(), 2);
+        assert_eq!(bundle.names().collect::<Vec<_>>(), vec!["place", "people"]);
+
+        let place = bundle.get("place").unwrap();
+        assert_eq!(place.exact_match("統計".chars()), Some(0));
+        assert_eq!(place.exact_match("世界".chars()), None);
+        assert!(bundle.get("time").is_none());
+    }
+
+    #[test]
+    fn test_serialize_payloads() {
+        let mut bundle = TrieBundle::new();
+        bundle.add(
+            "reading",
+            crate::Builder::new()
+                .build_from_payloads([("世界", "せかい"), ("国民", "こくみん")])
+                .unwrap()
+                .release_trie()
+                .unwrap(),
+        );
+        let bytes = bundle.serialize_to_vec();
+        let (other, _) = TrieBundle::try_deserialize_from_slice(&bytes).unwrap();
+        let reading = other.get("reading").unwrap();
+        assert_eq!(reading.payload("国民".chars()), Some("こくみん".as_bytes()));
+    }
+
+    #[test]
+    fn test_serialize() {
+        let bundle = make_bundle();
+        let mut bytes = bundle.serialize_to_vec();
+        bytes.push(42);
+
+        let (other, rest) = TrieBundle::try_deserialize_from_slice(&bytes).unwrap();
+        assert_eq!(rest, &[42]);
+        assert_eq!(other.names().collect::<Vec<_>>(), vec!["place", "people"]);
+        for name in ["place", "people"] {
+            assert_eq!(
+                other.get(name).unwrap().serialize_to_vec(),
+                bundle.get(name).unwrap().serialize_to_vec()
+            );
+        }
+        let people = other.get("people").unwrap();
+        assert_eq!(people.exact_match("市民".chars()), Some(1));
+
+        // Cuts at each field of the index, inside and between the tries, and before the last byte.
+        let place_len = bundle.get("place").unwrap().io_bytes();
+        let people_len = bundle.get("people").unwrap().io_bytes();
+        let header_len = 4 + (4 + "place".len() + 8) + (4 + "people".len() + 8);
+        let cuts = [
+            0,
+            2,
+            4,
+            8,
+            10,
+            13,
+            header_len - 1,
+            header_len,
+            header_len + place_len / 2,
+            header_len + place_len,
+            header_len + place_len + people_len / 2,
+            header_len + place_len + people_len - 1,
+        ];
+        assert_eq!(header_len + place_len + people_len, bytes.len() - 1);
+        for i in cuts {
+            assert!(TrieBundle::try_deserialize_from_slice(&bytes[..i]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_serialize_empty() {
+        let bytes = TrieBundle::new().serialize_to_vec();
+        let (other, rest) = TrieBundle::try_deserialize_from_slice(&bytes).unwrap();
+        assert!(other.is_empty());
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_deserialize_duplicate_names() {
+        let mut bundle = make_bundle();
+        bundle.entries[1].0 = "place".to_string();
+        let bytes = bundle.serialize_to_vec();
+        assert!(matches!(
+            TrieBundle::try_deserialize_from_slice(&bytes),
+            Err(CrawdadError::Corrupted(_))
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_write_and_read() {
+        let bundle = make_bundle();
+        let mut buf = vec![];
+        bundle.write(&mut buf).unwrap();
+
+        let other = TrieBundle::read(&mut buf.as_slice()).unwrap();
+        let place = other.get("place").unwrap();
+        assert_eq!(place.exact_match("世界中".chars()), Some(1));
+        let people = other.get("people").unwrap();
+        assert_eq!(people.exact_match("国民".chars()), Some(0));
+
+        buf.push(0);
+        let e = TrieBundle::read(&mut buf.as_slice()).err().unwrap();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
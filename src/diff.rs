@@ -0,0 +1,152 @@
+//! Difference between key-value sets of two tries.
+use crate::trie::Iter;
+use crate::Trie;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use core::cmp::Ordering;
+use core::iter::Peekable;
+
+/// Difference between key-value sets of two tries, created by [`diff()`].
+///
+/// Every list is sorted in the lexicographical order of keys.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct TrieDiff {
+    /// Keys and their values contained only in the new trie.
+    pub added: Vec<(String, u32)>,
+
+    /// Keys and their values contained only in the old trie.
+    pub removed: Vec<(String, u32)>,
+
+    /// Keys contained in both tries with different values,
+    /// in the form of `(key, old_value, new_value)`.
+    pub changed: Vec<(String, u32, u32)>,
+}
+
+impl TrieDiff {
+    /// Checks if the two tries have the same key-value set.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Computes the difference of key-value sets from `old` to `new`.
+///
+/// # Arguments
+///
+/// - `old`: Trie of the old version.
+/// - `new`: Trie of the new version.
+///
+/// # Examples
+///
+/// ```
+/// use crawdad::Trie;
+///
+/// let old = Trie::from_records([("世界", 0), ("世界中", 1), ("国民", 2)]).unwrap();
+/// let new = Trie::from_records([("世界", 0), ("世界中", 3), ("統計", 4)]).unwrap();
+///
+/// let diff = crawdad::diff(&old, &new);
+/// assert_eq!(diff.added, vec![("統計".to_string(), 4)]);
+/// assert_eq!(diff.removed, vec![("国民".to_string(), 2)]);
+/// assert_eq!(diff.changed, vec![("世界中".to_string(), 1, 3)]);
+/// ```
+pub fn diff(old: &Trie, new: &Trie) -> TrieDiff {
+    let mut result = TrieDiff::default();
+    for merged in merge(old, new) {
+        match merged {
+            Merged::Left(record) => result.removed.push(record),
+            Merged::Right(record) => result.added.push(record),
+            Merged::Both(key, old_value, new_value) => {
+                if old_value != new_value {
+                    result.changed.push((key, old_value, new_value));
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Record yielded by [`MergeIter`].
+pub(crate) enum Merged {
+    /// Key and value contained only in the left trie.
+    Left((String, u32)),
+
+    /// Key and value contained only in the right trie.
+    Right((String, u32)),
+
+    /// Key contained in both tries with its values in the left and right tries.
+    Both(String, u32, u32),
+}
+
+/// Iterator merging records of two tries in the lexicographical order of keys,
+/// created by [`merge()`].
+pub(crate) struct MergeIter<'t> {
+    left: Peekable<Iter<'t>>,
+    right: Peekable<Iter<'t>>,
+}
+
+impl Iterator for MergeIter<'_> {
+    type Item = Merged;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ord = match (self.left.peek(), self.right.peek()) {
+            (Some((left_key, _)), Some((right_key, _))) => left_key.cmp(right_key),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return None,
+        };
+        Some(match ord {
+            Ordering::Less => Merged::Left(self.left.next().unwrap()),
+            Ordering::Greater => Merged::Right(self.right.next().unwrap()),
+            Ordering::Equal => {
+                let (key, left_value) = self.left.next().unwrap();
+                let (_, right_value) = self.right.next().unwrap();
+                Merged::Both(key, left_value, right_value)
+            }
+        })
+    }
+}
+
+/// Merges records of two tries in the lexicographical order of keys.
+pub(crate) fn merge<'t>(left: &'t Trie, right: &'t Trie) -> MergeIter<'t> {
+    MergeIter {
+        left: left.iter().peekable(),
+        right: right.iter().peekable(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_diff() {
+        let old =
+            Trie::from_records([("世界", 0), ("世界中", 1), ("世論調査", 2), ("国民", 3)]).unwrap();
+        let new =
+            Trie::from_records([("世", 5), ("世界", 0), ("世界中", 4), ("統計調査", 6)]).unwrap();
+
+        let d = diff(&old, &new);
+        assert_eq!(
+            d.added,
+            vec![("世".to_string(), 5), ("統計調査".to_string(), 6)]
+        );
+        assert_eq!(
+            d.removed,
+            vec![("世論調査".to_string(), 2), ("国民".to_string(), 3)]
+        );
+        assert_eq!(d.changed, vec![("世界中".to_string(), 1, 4)]);
+        assert!(!d.is_empty());
+    }
+
+    #[test]
+    fn test_diff_same() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let old = Trie::from_keys(&keys).unwrap();
+        let new = Trie::from_keys(&keys).unwrap();
+        assert!(diff(&old, &new).is_empty());
+    }
+}
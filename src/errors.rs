@@ -1,11 +1,16 @@
 //! Definition of errors.
 use core::{fmt, result};
 
+use alloc::string::String;
+
 /// A specialized Result type for Crawdad.
 pub type Result<T, E = CrawdadError> = result::Result<T, E>;
 
 /// Errors in crawdad.
+///
+/// New kinds of errors may be added in future versions.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum CrawdadError {
     /// Contains [`InputError`].
     Input(InputError),
@@ -15,6 +20,21 @@ pub enum CrawdadError {
 
     /// Contains [`ScaleError`].
     Scale(ScaleError),
+
+    /// Contains [`CorruptedError`].
+    Corrupted(CorruptedError),
+
+    /// Contains [`UnsupportedVersionError`].
+    UnsupportedVersion(UnsupportedVersionError),
+
+    /// Contains [`KeyTooLongError`].
+    KeyTooLong(KeyTooLongError),
+
+    /// Contains [`TooManyNodesError`].
+    TooManyNodes(TooManyNodesError),
+
+    /// Contains [`UnsortedKeyError`].
+    UnsortedKey(UnsortedKeyError),
 }
 
 impl fmt::Display for CrawdadError {
@@ -23,6 +43,11 @@ impl fmt::Display for CrawdadError {
             Self::Input(e) => e.fmt(f),
             Self::Setup(e) => e.fmt(f),
             Self::Scale(e) => e.fmt(f),
+            Self::Corrupted(e) => e.fmt(f),
+            Self::UnsupportedVersion(e) => e.fmt(f),
+            Self::KeyTooLong(e) => e.fmt(f),
+            Self::TooManyNodes(e) => e.fmt(f),
+            Self::UnsortedKey(e) => e.fmt(f),
         }
     }
 }
@@ -37,6 +62,25 @@ impl CrawdadError {
     pub(crate) const fn scale(arg: &'static str, max: u32) -> Self {
         Self::Scale(ScaleError { arg, max })
     }
+    pub(crate) const fn corrupted(msg: &'static str) -> Self {
+        Self::Corrupted(CorruptedError { msg })
+    }
+    pub(crate) const fn unsupported_version(found: u32, supported: u32) -> Self {
+        Self::UnsupportedVersion(UnsupportedVersionError { found, supported })
+    }
+    pub(crate) const fn key_too_long(index: usize, len: usize, limit: usize) -> Self {
+        Self::KeyTooLong(KeyTooLongError { index, len, limit })
+    }
+    pub(crate) const fn too_many_nodes(count: u32, limit: u32) -> Self {
+        Self::TooManyNodes(TooManyNodesError { count, limit })
+    }
+    pub(crate) fn unsorted_key(index: usize, key: &str, prev_key: &str) -> Self {
+        Self::UnsortedKey(UnsortedKeyError {
+            index,
+            key: key.into(),
+            prev_key: prev_key.into(),
+        })
+    }
 }
 
 /// Error used when the input argument is invalid.
@@ -79,3 +123,147 @@ impl fmt::Display for ScaleError {
         )
     }
 }
+
+/// Error used when the data structure violates its invariants, e.g., after loading broken bytes.
+#[derive(Debug)]
+pub struct CorruptedError {
+    msg: &'static str,
+}
+
+impl fmt::Display for CorruptedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CorruptedError: {}", self.msg)
+    }
+}
+
+/// Error used when serialized bytes have a format version not supported by this crate.
+#[derive(Debug)]
+pub struct UnsupportedVersionError {
+    found: u32,
+    supported: u32,
+}
+
+impl UnsupportedVersionError {
+    /// Returns the format version of the bytes.
+    pub const fn found(&self) -> u32 {
+        self.found
+    }
+
+    /// Returns the format version supported by this crate.
+    pub const fn supported(&self) -> u32 {
+        self.supported
+    }
+}
+
+impl fmt::Display for UnsupportedVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "UnsupportedVersionError: format version {} is found, but {} is supported",
+            self.found, self.supported
+        )
+    }
+}
+
+/// Error used when a key is longer than the limit given by
+/// [`Builder::max_key_chars()`](crate::Builder::max_key_chars).
+#[derive(Debug)]
+pub struct KeyTooLongError {
+    index: usize,
+    len: usize,
+    limit: usize,
+}
+
+impl KeyTooLongError {
+    /// Returns the index of the key in the input order.
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the length of the key in characters.
+    #[allow(clippy::len_without_is_empty)]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the limit of the length in characters.
+    pub const fn limit(&self) -> usize {
+        self.limit
+    }
+}
+
+impl fmt::Display for KeyTooLongError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "KeyTooLongError: the {}-th key has {} characters, exceeding the limit {}",
+            self.index, self.len, self.limit
+        )
+    }
+}
+
+/// Error used when the node array under construction would exceed the limit of node indices,
+/// which keeps them from aliasing the invalid index and the flags.
+#[derive(Debug)]
+pub struct TooManyNodesError {
+    count: u32,
+    limit: u32,
+}
+
+impl TooManyNodesError {
+    /// Returns the number of nodes that the construction tried to allocate.
+    pub const fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Returns the limit of the number of nodes.
+    pub const fn limit(&self) -> u32 {
+        self.limit
+    }
+}
+
+impl fmt::Display for TooManyNodesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "TooManyNodesError: {} nodes are required, exceeding the limit {}",
+            self.count, self.limit
+        )
+    }
+}
+
+/// Error used when a key pushed into [`StreamingBuilder`](crate::StreamingBuilder)
+/// is not greater than the previous one.
+#[derive(Debug)]
+pub struct UnsortedKeyError {
+    index: usize,
+    key: String,
+    prev_key: String,
+}
+
+impl UnsortedKeyError {
+    /// Returns the index of the key in the pushed order.
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the rejected key.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the previously pushed key.
+    pub fn prev_key(&self) -> &str {
+        &self.prev_key
+    }
+}
+
+impl fmt::Display for UnsortedKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "UnsortedKeyError: the {}-th key {:?} is not greater than the previous key {:?}",
+            self.index, self.key, self.prev_key
+        )
+    }
+}
@@ -0,0 +1,144 @@
+//! Header of serialized data structures.
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::errors::{CrawdadError, Result};
+use crate::utils;
+
+/// Magic bytes at the beginning of serialized data structures.
+const MAGIC: [u8; 4] = *b"CRWD";
+
+/// Version of the current format.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Version assigned to the headerless format written by crawdad 0.4 and earlier.
+pub const LEGACY_VERSION: u32 = 1;
+
+/// Number of bytes of the magic and the format version.
+const VERSION_BYTES: usize = MAGIC.len() + 4;
+
+/// Version of the crate written in the header, e.g., `0.5.0` or `0.5.0-rc.1`.
+const PRODUCER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Number of bytes of the header written by this crate,
+/// followed by the length-prefixed version of the crate that produced the bytes.
+pub const HEADER_BYTES: usize = VERSION_BYTES + 4 + PRODUCER_VERSION.len();
+
+pub fn serialize_header(dest: &mut Vec<u8>) {
+    dest.extend_from_slice(&MAGIC);
+    dest.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    dest.extend_from_slice(&u32::try_from(PRODUCER_VERSION.len()).unwrap().to_le_bytes());
+    dest.extend_from_slice(PRODUCER_VERSION.as_bytes());
+}
+
+/// Returns the version of the crate that produced serialized bytes as it is,
+/// or [`None`] if the header of [`FORMAT_VERSION`] is not found.
+pub fn producer_version(source: &[u8]) -> Option<String> {
+    if version(source) != FORMAT_VERSION {
+        return None;
+    }
+    let mut source = &source[VERSION_BYTES..];
+    let len = utils::split_u32(&mut source).ok()?;
+    let bytes = utils::split_array(&mut source, len, 1).ok()?;
+    core::str::from_utf8(bytes).ok().map(Into::into)
+}
+
+/// Returns the format version of serialized bytes.
+///
+/// Bytes not starting with the magic are taken as [`LEGACY_VERSION`].
+/// The magic never appears at the beginning of the legacy format,
+/// because it would encode a mapping table longer than the number of characters.
+pub fn version(source: &[u8]) -> u32 {
+    if source.len() < VERSION_BYTES || source[..MAGIC.len()] != MAGIC {
+        return LEGACY_VERSION;
+    }
+    u32::from_le_bytes(source[MAGIC.len()..VERSION_BYTES].try_into().unwrap())
+}
+
+/// Splits the header off `source`,
+/// or returns an error if its version is not [`FORMAT_VERSION`].
+pub fn split_header(source: &mut &[u8]) -> Result<()> {
+    let found = version(source);
+    if found != FORMAT_VERSION {
+        return Err(CrawdadError::unsupported_version(found, FORMAT_VERSION));
+    }
+    utils::split_bytes(source, VERSION_BYTES)?;
+    let len = utils::split_u32(source)?;
+    utils::split_array(source, len, 1).map(|_| ())
+}
+
+/// Splits the header off `source` like [`split_header()`], but panics on error.
+pub fn skip_header(mut source: &[u8]) -> &[u8] {
+    let found = version(source);
+    assert_eq!(found, FORMAT_VERSION, "unsupported format version.");
+    split_header(&mut source).expect("the header is broken.");
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_header() {
+        let mut bytes = vec![];
+        serialize_header(&mut bytes);
+        assert_eq!(bytes.len(), HEADER_BYTES);
+        assert_eq!(version(&bytes), FORMAT_VERSION);
+
+        bytes.push(42);
+        let mut source = &bytes[..];
+        split_header(&mut source).unwrap();
+        assert_eq!(source, &[42]);
+        assert_eq!(skip_header(&bytes), &[42]);
+
+        // A truncated header of the current version is not taken as the legacy format.
+        let mut source = &bytes[..HEADER_BYTES - 1];
+        assert_eq!(version(source), FORMAT_VERSION);
+        assert!(matches!(
+            split_header(&mut source),
+            Err(CrawdadError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn test_producer_version() {
+        let mut bytes = vec![];
+        serialize_header(&mut bytes);
+        assert_eq!(producer_version(&bytes).unwrap(), env!("CARGO_PKG_VERSION"));
+        assert_eq!(producer_version(&bytes[..HEADER_BYTES - 1]), None);
+        assert_eq!(producer_version(&[0; HEADER_BYTES]), None);
+
+        // Pre-release and build metadata are kept.
+        let mut bytes = bytes[..VERSION_BYTES].to_vec();
+        let full = "1.23.4-rc.1+build.5";
+        bytes.extend_from_slice(&u32::try_from(full.len()).unwrap().to_le_bytes());
+        bytes.extend_from_slice(full.as_bytes());
+        bytes.push(42);
+        assert_eq!(producer_version(&bytes).unwrap(), full);
+        let mut source = &bytes[..];
+        split_header(&mut source).unwrap();
+        assert_eq!(source, &[42]);
+        assert_eq!(skip_header(&bytes), &[42]);
+
+        // A version of broken UTF-8 is not returned, but the header can be split.
+        let end = bytes.len() - 2;
+        bytes[end] = 0xff;
+        assert_eq!(producer_version(&bytes), None);
+        assert!(split_header(&mut &bytes[..]).is_ok());
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        assert_eq!(version(&bytes), 3);
+        let e = split_header(&mut &bytes[..]).unwrap_err();
+        assert!(matches!(e, CrawdadError::UnsupportedVersion(_)));
+
+        // Bytes without the magic are in the legacy format.
+        assert_eq!(version(&[]), LEGACY_VERSION);
+        assert_eq!(version(&[0; HEADER_BYTES]), LEGACY_VERSION);
+        assert_eq!(version(&MAGIC), LEGACY_VERSION);
+    }
+}
@@ -0,0 +1,156 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use core::cmp::Ordering;
+use core::mem::size_of;
+
+use crate::errors::{CrawdadError, Result};
+use crate::OFFSET_MASK;
+
+/// Original keys kept in the lexicographical order,
+/// retrieved from values by binary search in O(log n) time.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct KeyStore {
+    // Concatenation of keys.
+    blob: String,
+    // The i-th key is blob[offsets[i]..offsets[i + 1]].
+    offsets: Vec<u32>,
+    // The i-th key is associated with values[i].
+    values: Vec<u32>,
+    // Pairs of a value and the slot of a key associated with it, sorted for binary search.
+    slots: Vec<(u32, u32)>,
+}
+
+impl KeyStore {
+    /// Creates a store from records sorted by keys.
+    pub fn new<'a, I>(records: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (&'a [char], u32)>,
+    {
+        let mut blob = String::new();
+        let mut offsets = vec![0];
+        let mut values = vec![];
+        let mut slots = vec![];
+        for (i, (key, value)) in records.into_iter().enumerate() {
+            blob.extend(key.iter());
+            if usize::try_from(OFFSET_MASK).unwrap() < blob.len() {
+                return Err(CrawdadError::scale("length of kept keys", OFFSET_MASK));
+            }
+            offsets.push(u32::try_from(blob.len()).unwrap());
+            values.push(value);
+            slots.push((value, u32::try_from(i).unwrap()));
+        }
+        slots.sort_unstable();
+        Ok(Self {
+            blob,
+            offsets,
+            values,
+            slots,
+        })
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    #[inline(always)]
+    pub fn value(&self, slot: usize) -> u32 {
+        self.values[slot]
+    }
+
+    #[inline(always)]
+    pub fn key(&self, slot: usize) -> &str {
+        let beg = usize::try_from(self.offsets[slot]).unwrap();
+        let end = usize::try_from(self.offsets[slot + 1]).unwrap();
+        &self.blob[beg..end]
+    }
+
+    /// Returns the lexicographically smallest key associated with a value if exists.
+    #[inline(always)]
+    pub fn key_of_value(&self, value: u32) -> Option<&str> {
+        let i = self.slots.partition_point(|&(v, _)| v < value);
+        let &(v, slot) = self.slots.get(i)?;
+        (v == value).then(|| self.key(usize::try_from(slot).unwrap()))
+    }
+
+    /// Returns the slot of a key if exists.
+    pub fn find(&self, key: &str) -> Option<usize> {
+        let mut lo = 0;
+        let mut hi = self.len();
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match self.key(mid).cmp(key) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    /// Associates the key in a slot with a new value.
+    pub fn update_value(&mut self, slot: usize, value: u32) {
+        let old_pair = (self.values[slot], u32::try_from(slot).unwrap());
+        let new_pair = (value, old_pair.1);
+        self.values[slot] = value;
+        let i = self.slots.binary_search(&old_pair).unwrap();
+        self.slots.remove(i);
+        let j = self.slots.binary_search(&new_pair).unwrap_err();
+        self.slots.insert(j, new_pair);
+    }
+
+    #[inline]
+    pub fn heap_bytes(&self) -> usize {
+        self.blob.len()
+            + (self.offsets.len() + self.values.len()) * size_of::<u32>()
+            + self.slots.len() * size_of::<(u32, u32)>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_store(records: &[(&str, u32)]) -> KeyStore {
+        let chars: Vec<(Vec<char>, u32)> = records
+            .iter()
+            .map(|&(k, v)| (k.chars().collect(), v))
+            .collect();
+        KeyStore::new(chars.iter().map(|(k, v)| (k.as_slice(), *v))).unwrap()
+    }
+
+    #[test]
+    fn test_key_of_value() {
+        let store = make_store(&[("世界", 3), ("世界中", 1), ("国民", 3)]);
+        assert_eq!(store.key_of_value(1), Some("世界中"));
+        assert_eq!(store.key_of_value(3), Some("世界"));
+        assert_eq!(store.key_of_value(0), None);
+        assert_eq!(store.key_of_value(2), None);
+        assert_eq!(store.key_of_value(4), None);
+    }
+
+    #[test]
+    fn test_sparse_values() {
+        let store = make_store(&[("a", OFFSET_MASK), ("b", 1 << 28)]);
+        assert_eq!(store.key_of_value(OFFSET_MASK), Some("a"));
+        assert_eq!(store.key_of_value(1 << 28), Some("b"));
+        assert_eq!(store.key_of_value(0), None);
+        // The size is proportional to the number of keys, not to the maximum value.
+        assert!(store.heap_bytes() < 64);
+    }
+
+    #[test]
+    fn test_update_value() {
+        let mut store = make_store(&[("世界", 3), ("世界中", 1), ("国民", 3)]);
+        store.update_value(0, 1 << 30);
+        assert_eq!(store.key_of_value(3), Some("国民"));
+        assert_eq!(store.key_of_value(1 << 30), Some("世界"));
+        store.update_value(2, 1);
+        assert_eq!(store.key_of_value(3), None);
+        assert_eq!(store.key_of_value(1), Some("世界中"));
+        store.update_value(1, 2);
+        assert_eq!(store.key_of_value(1), Some("国民"));
+        assert!(store.heap_bytes() < 128);
+    }
+}
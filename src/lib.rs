@@ -29,8 +29,8 @@
 //!
 //! ## Finding all occurrences of keys in an input text
 //!
-//! To search for all occurrences of registered keys in an input text,
-//! use [`Trie::common_prefix_search()`] for all starting positions in the text.
+//! To search for all occurrences of registered keys in an input text, use [`Trie::scan()`],
+//! which performs [`Trie::common_prefix_search()`] for all starting positions in the text.
 //!
 //! ```
 //! use crawdad::Trie;
@@ -39,13 +39,7 @@
 //! let trie = Trie::from_keys(&keys).unwrap();
 //!
 //! let haystack: Vec<char> = "国民が世界中にて".chars().collect();
-//! let mut matches = vec![];
-//!
-//! for i in 0..haystack.len() {
-//!     for (v, j) in trie.common_prefix_search(haystack[i..].iter().copied()) {
-//!         matches.push((v, i..i + j));
-//!     }
-//! }
+//! let matches: Vec<_> = trie.scan(&haystack).collect();
 //!
 //! assert_eq!(
 //!     matches,
@@ -53,6 +47,23 @@
 //! );
 //! ```
 //!
+//! ## Associating values wider than 31 bits
+//!
+//! Values stored in the tries must be no greater than `i32::MAX`.
+//! To associate wider values such as 64-bit identifiers, keep them in a side array
+//! and let the keys hold their indices, which costs extra 8 bytes per key.
+//!
+//! ```
+//! use crawdad::Trie;
+//!
+//! let records = vec![("世界", 1u64 << 40), ("世界中", 42), ("国民", u64::MAX)];
+//! let trie = Trie::from_keys(records.iter().map(|&(k, _)| k)).unwrap();
+//! let values: Vec<u64> = records.iter().map(|&(_, v)| v).collect();
+//!
+//! let value = trie.exact_match("国民".chars()).map(|i| values[i as usize]);
+//! assert_eq!(value, Some(u64::MAX));
+//! ```
+//!
 //! ## Serializing and deserializing the data structure
 //!
 //! To serialize/deserialize the data structure into/from a byte sequence,
@@ -69,6 +80,13 @@
 //!
 //! assert_eq!(trie.io_bytes(), other.io_bytes());
 //! ```
+//!
+//! The bytes start with a header of the format version and the version of crawdad
+//! that produced them, which can be read by [`Trie::producer_version()`].
+//! All the integers are written in little-endian regardless of the host,
+//! so the bytes can be shared between machines of different byte orders.
+//! Bytes written by crawdad 0.4 and earlier have no header and must be loaded
+//! with [`Trie::migrate_from_slice()`], which upgrades them to the current format.
 #![deny(missing_docs)]
 #![no_std]
 
@@ -81,12 +99,22 @@ compile_error!("`alloc` feature is currently required to build this crate");
 #[macro_use]
 extern crate alloc;
 
-mod builder;
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod builder;
+pub mod bundle;
+pub mod diff;
 pub mod errors;
+mod format;
+mod keys;
 mod mapper;
 pub mod mptrie;
+mod payloads;
+mod traverse;
 pub mod trie;
 mod utils;
+mod validate;
 
 pub(crate) const OFFSET_MASK: u32 = 0x7fff_ffff;
 pub(crate) const INVALID_IDX: u32 = 0xffff_ffff;
@@ -96,6 +124,9 @@ pub(crate) const END_CODE: u32 = 0;
 /// Special terminator, which must not be contained in keys.
 pub const END_MARKER: char = '\u{ffff}';
 
+pub use builder::{prepare_keys, Builder, CodeOrder, SizeEstimate, StreamingBuilder};
+pub use bundle::TrieBundle;
+pub use diff::{diff, TrieDiff};
 pub use mptrie::MpTrie;
 pub use trie::Trie;
 
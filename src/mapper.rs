@@ -2,8 +2,13 @@ use alloc::vec::Vec;
 
 use core::mem::size_of;
 
+use crate::errors::Result;
+use crate::{utils, END_CODE, END_MARKER};
+
 pub const INVALID_CODE: u32 = u32::MAX;
 
+const ASCII_SIZE: usize = 0x80;
+
 #[derive(Default, Clone, Debug, PartialEq, Eq)]
 pub struct CodeMapper {
     table: Vec<u32>,
@@ -11,6 +16,8 @@ pub struct CodeMapper {
 }
 
 impl CodeMapper {
+    /// Creates a mapper assigning smaller codes to more frequent characters,
+    /// where ties are broken by code points.
     pub fn new(freqs: &[u32]) -> Self {
         let sorted = {
             let mut sorted = vec![];
@@ -20,28 +27,143 @@ impl CodeMapper {
             sorted.sort_unstable_by(|(c1, f1), (c2, f2)| f2.cmp(f1).then_with(|| c1.cmp(c2)));
             sorted
         };
+        Self::from_sorted(freqs.len(), sorted.into_iter().map(|(c, _)| c))
+    }
+
+    /// Creates a mapper assigning codes to characters in the order of code points,
+    /// except that the most frequent one, i.e., [`END_MARKER`], is assigned [`END_CODE`].
+    pub fn by_codepoint(freqs: &[u32]) -> Self {
+        let end_marker = freqs
+            .iter()
+            .enumerate()
+            .max_by(|(c1, f1), (c2, f2)| f1.cmp(f2).then_with(|| c2.cmp(c1)))
+            .map(|(c, _)| c);
+        let others = freqs
+            .iter()
+            .enumerate()
+            .filter(|&(c, &f)| f != 0 && Some(c) != end_marker)
+            .map(|(c, _)| c);
+        Self::from_sorted(freqs.len(), end_marker.into_iter().chain(others))
+    }
 
-        let mut table = vec![INVALID_CODE; freqs.len()];
-        for (i, &(c, _)) in sorted.iter().enumerate() {
-            table[c] = i.try_into().unwrap();
+    /// Creates a mapper assigning codes `0, 1, ...` to characters in the given order.
+    fn from_sorted<I>(table_len: usize, sorted: I) -> Self
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        let mut table = vec![INVALID_CODE; table_len];
+        let mut alphabet_size = 0;
+        for c in sorted {
+            table[c] = alphabet_size;
+            alphabet_size += 1;
         }
         Self {
             table,
-            alphabet_size: sorted.len().try_into().unwrap(),
+            alphabet_size,
+        }
+    }
+
+    /// Creates a mapper assigning `c + 1` to each ASCII character `c`,
+    /// or returns `None` if `freqs` has a non-ASCII character other than [`END_MARKER`].
+    ///
+    /// Code zero is left for [`END_MARKER`], which is not contained in the table.
+    pub fn ascii_identity(freqs: &[u32]) -> Option<Self> {
+        let end_marker = usize::try_from(u32::from(END_MARKER)).unwrap();
+        let is_ascii = freqs
+            .iter()
+            .enumerate()
+            .all(|(c, &f)| f == 0 || c < ASCII_SIZE || c == end_marker);
+        is_ascii.then(|| Self {
+            table: (1..=ASCII_SIZE).map(|c| c.try_into().unwrap()).collect(),
+            alphabet_size: u32::try_from(ASCII_SIZE).unwrap() + 1,
+        })
+    }
+
+    /// Creates a mapper from a table of codes indexed by characters,
+    /// taking the alphabet size as one more than the largest code.
+    pub fn from_table(table: Vec<u32>) -> Self {
+        let alphabet_size = table
+            .iter()
+            .filter(|&&code| code != INVALID_CODE)
+            .max()
+            .map_or(0, |&code| code.saturating_add(1));
+        Self {
+            table,
+            alphabet_size,
         }
     }
 
+    /// Removes all the characters, keeping the allocated table.
+    pub fn clear(&mut self) {
+        self.table.clear();
+        self.alphabet_size = 0;
+    }
+
+    /// Returns the table of codes indexed by characters,
+    /// where [`INVALID_CODE`] is assigned to characters not in the alphabet.
+    #[inline]
+    pub fn table(&self) -> &[u32] {
+        &self.table
+    }
+
     #[inline]
     pub const fn alphabet_size(&self) -> u32 {
         self.alphabet_size
     }
 
+    /// Checks if every code is assigned to a valid character and is less than the alphabet size.
+    pub fn is_closed(&self) -> bool {
+        self.table.iter().enumerate().all(|(c, &code)| {
+            code == INVALID_CODE
+                || (code < self.alphabet_size
+                    && u32::try_from(c).ok().and_then(char::from_u32).is_some())
+        })
+    }
+
+    /// Returns the code of a character if exists.
+    ///
+    /// [`END_CODE`] is never returned, so [`END_MARKER`] in a query does not follow
+    /// the edge to a leaf.
     #[inline(always)]
     pub fn get(&self, c: char) -> Option<u32> {
         self.table
             .get(usize::try_from(u32::from(c)).unwrap())
             .copied()
-            .filter(|&code| code != INVALID_CODE)
+            .filter(|&code| code != INVALID_CODE && code != END_CODE)
+    }
+
+    /// Returns the table from codes to characters.
+    ///
+    /// [`END_CODE`] is always decoded into [`END_MARKER`].
+    pub fn decoder(&self) -> Vec<char> {
+        let mut decoder = vec![END_MARKER; usize::try_from(self.alphabet_size).unwrap()];
+        for (c, &code) in self.table.iter().enumerate() {
+            if code != INVALID_CODE {
+                let c = char::from_u32(u32::try_from(c).unwrap()).unwrap();
+                decoder[usize::try_from(code).unwrap()] = c;
+            }
+        }
+        decoder
+    }
+
+    /// Returns the characters whose codes satisfy a predicate in ascending order.
+    ///
+    /// It takes time linear in the size of the table.
+    pub fn decode_if<F>(&self, mut f: F) -> Vec<char>
+    where
+        F: FnMut(u32) -> bool,
+    {
+        let mut chars = vec![];
+        for (c, &code) in self.table.iter().enumerate() {
+            if code != INVALID_CODE && f(code) {
+                chars.push(char::from_u32(u32::try_from(c).unwrap()).unwrap());
+            }
+        }
+        chars
+    }
+
+    pub fn prewarm(&self) {
+        crate::utils::touch_pages(&self.table);
     }
 
     #[inline]
@@ -62,6 +184,24 @@ impl CodeMapper {
         dest.extend_from_slice(&self.alphabet_size.to_le_bytes());
     }
 
+    /// Deserializes the mapper like [`CodeMapper::deserialize_from_slice()`],
+    /// but returns an error instead of panicking if `source` is too short.
+    pub fn try_deserialize_from_slice(mut source: &[u8]) -> Result<(Self, &[u8])> {
+        let len = utils::split_u32(&mut source)?;
+        let table = utils::split_array(&mut source, len, size_of::<u32>())?
+            .chunks_exact(size_of::<u32>())
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        let alphabet_size = utils::split_u32(&mut source)?;
+        Ok((
+            Self {
+                table,
+                alphabet_size,
+            },
+            source,
+        ))
+    }
+
     pub fn deserialize_from_slice(mut source: &[u8]) -> (Self, &[u8]) {
         let table = {
             let len = u32::from_le_bytes(source[..4].try_into().unwrap()) as usize;
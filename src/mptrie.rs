@@ -1,14 +1,18 @@
 //! A minimal-prefix trie form that is memory-efficient for long strings.
-use crate::builder::Builder;
+use crate::builder::{BuildOptions, Builder};
 use crate::errors::Result;
 use crate::mapper::CodeMapper;
-use crate::{utils, Node};
+use crate::traverse::LeafIter;
+use crate::{format, utils, validate, Node};
 
 use crate::END_CODE;
 
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
 use core::mem;
+use core::ops::ControlFlow;
 
 /// A minimal-prefix trie form that is memory-efficient for long strings.
 pub struct MpTrie {
@@ -17,17 +21,21 @@ pub struct MpTrie {
     pub(crate) tails: Vec<u8>,
     pub(crate) code_size: u8,
     pub(crate) value_size: u8,
+    pub(crate) options: BuildOptions,
 }
 
 impl MpTrie {
     /// Creates a new [`MpTrie`] from input keys.
     ///
-    /// Values in `[0..n-1]` will be associated with keys in the lexicographical order,
+    /// Values in `[0..n-1]` will be associated with keys in the input order,
     /// where `n` is the number of keys.
+    /// Keys can be given in any order; they are sorted by the builder.
+    /// If they are sorted by the [`Ord`] of [`str`] (e.g., with [`prepare_keys()`](crate::prepare_keys)),
+    /// the values follow the lexicographical order.
     ///
     /// # Arguments
     ///
-    /// - `keys`: Sorted list of string keys.
+    /// - `keys`: List of string keys.
     ///
     /// # Errors
     ///
@@ -99,6 +107,9 @@ impl MpTrie {
 
     /// Serializes the data structure into a [`Vec`].
     ///
+    /// The bytes start with a header of the format version,
+    /// which is checked by the deserialization.
+    ///
     /// # Examples
     ///
     /// ```
@@ -110,6 +121,7 @@ impl MpTrie {
     /// ```
     pub fn serialize_to_vec(&self) -> Vec<u8> {
         let mut dest = Vec::with_capacity(self.io_bytes());
+        format::serialize_header(&mut dest);
         self.mapper.serialize_into_vec(&mut dest);
         dest.extend_from_slice(&u32::try_from(self.nodes.len()).unwrap().to_le_bytes());
         for node in &self.nodes {
@@ -132,6 +144,13 @@ impl MpTrie {
     ///
     /// A tuple of the data structure and the slice not used for the deserialization.
     ///
+    /// # Panics
+    ///
+    /// It panics if the format version of `source` is not supported,
+    /// or might panic if `source` is broken.
+    /// Use [`MpTrie::try_deserialize_from_slice()`] for untrusted bytes
+    /// and [`MpTrie::migrate_from_slice()`] for bytes of an older format.
+    ///
     /// # Examples
     ///
     /// ```
@@ -146,6 +165,7 @@ impl MpTrie {
     /// assert_eq!(trie.io_bytes(), other.io_bytes());
     /// ```
     pub fn deserialize_from_slice(source: &[u8]) -> (Self, &[u8]) {
+        let source = format::skip_header(source);
         let (mapper, mut source) = CodeMapper::deserialize_from_slice(source);
         let nodes = {
             let len = u32::from_le_bytes(source[..4].try_into().unwrap()) as usize;
@@ -175,11 +195,170 @@ impl MpTrie {
                 tails,
                 code_size,
                 value_size,
+                options: BuildOptions::default(),
             },
             &source[2..],
         )
     }
 
+    /// Deserializes the data structure from a given byte slice
+    /// while checking that it is not broken.
+    ///
+    /// Unlike [`MpTrie::deserialize_from_slice()`], this never panics on malformed bytes,
+    /// and the resulting trie passes [`MpTrie::validate()`].
+    /// It is suitable for loading bytes from untrusted sources.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - A source byte slice.
+    ///
+    /// # Returns
+    ///
+    /// A tuple of the data structure and the slice not used for the deserialization.
+    ///
+    /// # Errors
+    ///
+    /// [`CrawdadError`](crate::errors::CrawdadError) will be returned when
+    ///
+    /// - the format version of `source` is not supported
+    ///   ([`CrawdadError::UnsupportedVersion`](crate::errors::CrawdadError::UnsupportedVersion)), or
+    /// - `source` is too short or the deserialized data structure is broken
+    ///   ([`CrawdadError::Corrupted`](crate::errors::CrawdadError::Corrupted)).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::MpTrie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = MpTrie::from_keys(&keys).unwrap();
+    ///
+    /// let bytes = trie.serialize_to_vec();
+    /// let (other, _) = MpTrie::try_deserialize_from_slice(&bytes).unwrap();
+    /// assert_eq!(other.exact_match("世界中".chars()), Some(1));
+    ///
+    /// assert!(MpTrie::try_deserialize_from_slice(&bytes[..bytes.len() - 1]).is_err());
+    /// ```
+    pub fn try_deserialize_from_slice(mut source: &[u8]) -> Result<(Self, &[u8])> {
+        format::split_header(&mut source)?;
+        Self::try_deserialize_body(source)
+    }
+
+    /// Deserializes the data structure from a given byte slice in the current
+    /// or an older format, while checking that it is not broken.
+    ///
+    /// It accepts the headerless format written by crawdad 0.4 and earlier
+    /// (format version 1) as well as the current one.
+    /// The resulting trie is serialized in the current format by
+    /// [`MpTrie::serialize_to_vec()`], so this upgrades bytes of an older format.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - A source byte slice.
+    ///
+    /// # Returns
+    ///
+    /// A tuple of the data structure and the slice not used for the deserialization.
+    ///
+    /// # Errors
+    ///
+    /// See [`MpTrie::try_deserialize_from_slice()`].
+    pub fn migrate_from_slice(mut source: &[u8]) -> Result<(Self, &[u8])> {
+        if format::version(source) != format::LEGACY_VERSION {
+            format::split_header(&mut source)?;
+        }
+        Self::try_deserialize_body(source)
+    }
+
+    fn try_deserialize_body(source: &[u8]) -> Result<(Self, &[u8])> {
+        let (mapper, mut source) = CodeMapper::try_deserialize_from_slice(source)?;
+        let len = utils::split_u32(&mut source)?;
+        let nodes = utils::split_array(&mut source, len, Node::io_bytes())?
+            .chunks_exact(Node::io_bytes())
+            .map(|bytes| Node::deserialize(bytes.try_into().unwrap()))
+            .collect();
+        let len = utils::split_u32(&mut source)?;
+        let tails = utils::split_array(&mut source, len, 1)?.to_vec();
+        let sizes = utils::split_bytes(&mut source, 2)?;
+        let trie = Self {
+            mapper,
+            nodes,
+            tails,
+            code_size: sizes[0],
+            value_size: sizes[1],
+            options: BuildOptions::default(),
+        };
+        trie.validate()?;
+        Ok((trie, source))
+    }
+
+    /// Returns the version of crawdad that produced serialized bytes, e.g., `"0.5.0"`,
+    /// reading only the header.
+    ///
+    /// The version is recorded as it is, including pre-release and build metadata
+    /// such as `"0.5.0-rc.1"`.
+    ///
+    /// [`None`] is returned if the bytes do not start with a header of the current format,
+    /// e.g., bytes written by crawdad 0.4 and earlier.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - A source byte slice.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::MpTrie;
+    ///
+    /// let trie = MpTrie::from_keys(["世界", "世界中", "国民"]).unwrap();
+    /// let bytes = trie.serialize_to_vec();
+    ///
+    /// assert_eq!(MpTrie::producer_version(&bytes).unwrap(), env!("CARGO_PKG_VERSION"));
+    /// assert_eq!(MpTrie::producer_version(&bytes[4..]), None);
+    /// ```
+    pub fn producer_version(source: &[u8]) -> Option<String> {
+        format::producer_version(source)
+    }
+
+    /// Checks the structural invariants of the data structure.
+    ///
+    /// This is useful to verify a trie deserialized from untrusted bytes
+    /// before querying it, since queries on a broken trie may panic.
+    ///
+    /// # Errors
+    ///
+    /// [`CrawdadError::Corrupted`](crate::errors::CrawdadError::Corrupted) will be returned
+    /// on the first violation found, e.g., when
+    ///
+    /// - `check` of a node does not point to a valid parent,
+    /// - `base` of a node points out of bounds,
+    /// - leaf and has-leaf flags are inconsistent,
+    /// - some nodes are unreachable from the root, or
+    /// - a tail of a leaf is out of bounds or has an invalid code.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::MpTrie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = MpTrie::from_keys(&keys).unwrap();
+    ///
+    /// let bytes = trie.serialize_to_vec();
+    /// let (other, _) = MpTrie::deserialize_from_slice(&bytes);
+    /// assert!(other.validate().is_ok());
+    /// ```
+    pub fn validate(&self) -> Result<()> {
+        validate::validate_nodes(&self.mapper, &self.nodes)?;
+        validate::validate_tails(
+            &self.mapper,
+            &self.nodes,
+            &self.tails,
+            self.code_size,
+            self.value_size,
+        )
+    }
+
     /// Returns a value associated with an input key if exists.
     ///
     /// # Arguments
@@ -231,10 +410,41 @@ impl MpTrie {
         chars.next().is_none().then(|| tail_iter.value())
     }
 
+    /// Returns a value associated with an input key if exists,
+    /// together with the number of characters consumed in the search.
+    ///
+    /// The number is the length of the key in characters, obtained in the same walk.
+    ///
+    /// # Arguments
+    ///
+    /// - `key`: Search key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::MpTrie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = MpTrie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.exact_match_with_len("世界中".chars()), Some((1, 3)));
+    /// assert_eq!(trie.exact_match_with_len("日本中".chars()), None);
+    /// ```
+    pub fn exact_match_with_len<I>(&self, key: I) -> Option<(u32, usize)>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let mut len = 0;
+        self.exact_match(key.into_iter().inspect(|_| len += 1))
+            .map(|v| (v, len))
+    }
+
     /// Returns an iterator for common prefix search.
     ///
     /// The iterator reports all occurrences of keys starting from an input haystack, where
     /// an occurrence consists of its associated value and ending positoin in characters.
+    /// Occurrences are reported in strictly ascending order of their ending positions,
+    /// so the last one is the longest match.
     ///
     /// # Examples
     ///
@@ -261,7 +471,7 @@ impl MpTrie {
     ///     vec![(2, 0..2), (0, 3..5), (1, 3..6)]
     /// );
     /// ```
-    pub const fn common_prefix_search<I>(&self, haystack: I) -> CommonPrefixSearchIter<I> {
+    pub const fn common_prefix_search<I>(&self, haystack: I) -> CommonPrefixSearchIter<'_, I> {
         CommonPrefixSearchIter {
             haystack,
             haystack_pos: 0,
@@ -270,9 +480,284 @@ impl MpTrie {
         }
     }
 
+    /// Returns the shortest occurrence of keys starting from an input haystack if exists,
+    /// where an occurrence consists of its associated value and ending position in characters.
+    ///
+    /// The search stops as soon as the first key is found.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::MpTrie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = MpTrie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.shortest_prefix_match("世界中に".chars()), Some((0, 2)));
+    /// assert_eq!(trie.shortest_prefix_match("国".chars()), None);
+    /// ```
+    pub fn shortest_prefix_match<I>(&self, haystack: I) -> Option<(u32, usize)>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        self.common_prefix_search(haystack.into_iter()).next()
+    }
+
+    /// Returns the number of keys that are prefixes of an input haystack.
+    ///
+    /// It is equivalent to `common_prefix_search(haystack).count()`
+    /// but does not compute values of occurrences.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::MpTrie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = MpTrie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.common_prefix_count("世界中に".chars()), 2);
+    /// assert_eq!(trie.common_prefix_count("国".chars()), 0);
+    /// ```
+    pub fn common_prefix_count<I>(&self, haystack: I) -> usize
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let mut count = 0;
+        let mut node_idx = 0;
+        let mut chars = haystack.into_iter();
+        while let Some(c) = chars.next() {
+            if let Some(child_idx) = self
+                .mapper
+                .get(c)
+                .and_then(|mc| self.get_child_idx(node_idx, mc))
+            {
+                node_idx = child_idx;
+            } else {
+                break;
+            }
+            if self.is_leaf(node_idx) {
+                let tail_pos = usize::try_from(self.get_value(node_idx)).unwrap();
+                let matched = self
+                    .tail_iter(tail_pos)
+                    .all(|tc| chars.next().and_then(|c| self.mapper.get(c)) == Some(tc));
+                if matched {
+                    count += 1;
+                }
+                break;
+            } else if self.has_leaf(node_idx) {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Calls a closure on each occurrence of keys starting from an input haystack,
+    /// where an occurrence consists of its associated value and ending position in characters.
+    ///
+    /// Occurrences are visited in the same order as [`MpTrie::common_prefix_search()`].
+    /// If the closure returns [`ControlFlow::Break`], the search stops immediately
+    /// without descending further.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use core::ops::ControlFlow;
+    /// use crawdad::MpTrie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = MpTrie::from_keys(&keys).unwrap();
+    ///
+    /// let mut matches = vec![];
+    /// trie.for_each_common_prefix("世界中に".chars(), |v, j| {
+    ///     matches.push((v, j));
+    ///     ControlFlow::Break(())
+    /// });
+    /// assert_eq!(matches, vec![(0, 2)]);
+    /// ```
+    pub fn for_each_common_prefix<I, F>(&self, haystack: I, mut f: F)
+    where
+        I: IntoIterator<Item = char>,
+        F: FnMut(u32, usize) -> ControlFlow<()>,
+    {
+        for (v, j) in self.common_prefix_search(haystack.into_iter()) {
+            if f(v, j) == ControlFlow::Break(()) {
+                return;
+            }
+        }
+    }
+
+    /// Returns the characters that can follow an input prefix in some keys,
+    /// in ascending order.
+    ///
+    /// The result is empty if no key has the prefix or if the prefix is a key
+    /// extended by no other key. The end of a key is not reported as a character.
+    ///
+    /// # Arguments
+    ///
+    /// - `prefix`: Prefix of keys.
+    ///
+    /// # Note
+    ///
+    /// It takes time linear in the largest code point of characters in keys
+    /// to decode the children.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::MpTrie;
+    ///
+    /// let keys = vec!["世界", "世界中", "世論調査", "国民"];
+    /// let trie = MpTrie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.next_chars("世".chars()), vec!['界', '論']);
+    /// assert_eq!(trie.next_chars("世論".chars()), vec!['調']);
+    /// assert!(trie.next_chars("国民".chars()).is_empty());
+    /// ```
+    pub fn next_chars<I>(&self, prefix: I) -> Vec<char>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let mut node_idx = 0;
+        let mut chars = prefix.into_iter();
+
+        while !self.is_leaf(node_idx) {
+            if let Some(c) = chars.next() {
+                node_idx = match self
+                    .mapper
+                    .get(c)
+                    .and_then(|mc| self.get_child_idx(node_idx, mc))
+                {
+                    Some(child_idx) => child_idx,
+                    None => return vec![],
+                };
+            } else {
+                return self.mapper.decode_if(|code| {
+                    code != END_CODE && self.get_child_idx(node_idx, code).is_some()
+                });
+            }
+        }
+
+        // The rest of the prefix must be a prefix of the tail.
+        let tail_pos = usize::try_from(self.get_value(node_idx)).unwrap();
+        let mut tail_iter = self.tail_iter(tail_pos);
+        for c in chars {
+            let mc = self.mapper.get(c);
+            if tail_iter.next().filter(|&tc| Some(tc) == mc).is_none() {
+                return vec![];
+            }
+        }
+        tail_iter
+            .next()
+            .map_or_else(Vec::new, |tc| self.mapper.decode_if(|code| code == tc))
+    }
+
+    /// Returns the lexicographically smallest key having an input prefix and its associated value
+    /// if exist, without enumerating all the keys under the prefix.
+    ///
+    /// # Arguments
+    ///
+    /// - `prefix`: Prefix of keys.
+    ///
+    /// # Note
+    ///
+    /// It takes time linear in the largest code point of characters in keys
+    /// plus the alphabet size times the length of the resulting key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::MpTrie;
+    ///
+    /// let keys = vec!["世界", "世界中", "世論調査", "国民"];
+    /// let trie = MpTrie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(
+    ///     trie.min_key_with_prefix("世".chars()),
+    ///     Some(("世界".to_string(), 0))
+    /// );
+    /// assert_eq!(trie.min_key_with_prefix("日本".chars()), None);
+    /// ```
+    pub fn min_key_with_prefix<I>(&self, prefix: I) -> Option<(String, u32)>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        self.extreme_key_with_prefix(prefix, false)
+    }
+
+    /// Returns the lexicographically largest key having an input prefix and its associated value
+    /// if exist, without enumerating all the keys under the prefix.
+    ///
+    /// # Arguments
+    ///
+    /// - `prefix`: Prefix of keys.
+    ///
+    /// # Note
+    ///
+    /// It takes time linear in the largest code point of characters in keys
+    /// plus the alphabet size times the length of the resulting key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::MpTrie;
+    ///
+    /// let keys = vec!["世界", "世界中", "世論調査", "国民"];
+    /// let trie = MpTrie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(
+    ///     trie.max_key_with_prefix("世".chars()),
+    ///     Some(("世論調査".to_string(), 2))
+    /// );
+    /// assert_eq!(trie.max_key_with_prefix("日本".chars()), None);
+    /// ```
+    pub fn max_key_with_prefix<I>(&self, prefix: I) -> Option<(String, u32)>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        self.extreme_key_with_prefix(prefix, true)
+    }
+
+    /// Returns an iterator over keys and their associated values
+    /// in the lexicographical order of keys.
+    ///
+    /// Keys are restored by concatenating labels in the double array with their tails,
+    /// so the iterator yields the same sequence as [`Trie::iter()`](crate::Trie::iter)
+    /// for the same input.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::MpTrie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = MpTrie::from_keys(&keys).unwrap();
+    ///
+    /// let records: Vec<_> = trie.iter().collect();
+    /// assert_eq!(
+    ///     records,
+    ///     vec![
+    ///         ("世界".to_string(), 0),
+    ///         ("世界中".to_string(), 1),
+    ///         ("国民".to_string(), 2),
+    ///     ]
+    /// );
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// It takes `O(num_elems log num_elems)` time and `O(num_elems)` extra space
+    /// to create the iterator.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            trie: self,
+            leaves: LeafIter::new(&self.mapper, &self.nodes),
+        }
+    }
+
     #[inline(always)]
-    fn tail_iter(&self, tail_pos: usize) -> TailIter {
-        let tail_len = usize::try_from(self.tails[tail_pos]).unwrap();
+    fn tail_iter(&self, tail_pos: usize) -> TailIter<'_> {
+        let tail_len = usize::from(self.tails[tail_pos]);
         TailIter {
             trie: self,
             pos: tail_pos + 1,
@@ -280,6 +765,82 @@ impl MpTrie {
         }
     }
 
+    fn extreme_key_with_prefix<I>(&self, prefix: I, largest: bool) -> Option<(String, u32)>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let mut key = String::new();
+        let mut node_idx = 0;
+        let mut chars = prefix.into_iter();
+
+        while !self.is_leaf(node_idx) {
+            if let Some(c) = chars.next() {
+                node_idx = self
+                    .mapper
+                    .get(c)
+                    .and_then(|mc| self.get_child_idx(node_idx, mc))?;
+                key.push(c);
+            } else {
+                break;
+            }
+        }
+        let decoder = self.mapper.decoder();
+        while !self.is_leaf(node_idx) {
+            let (is_char, c, child_idx) = self.extreme_child(node_idx, &decoder, largest)?;
+            if is_char {
+                key.push(c);
+            }
+            node_idx = child_idx;
+        }
+
+        let parent_idx = self.get_check(node_idx);
+        if self.has_leaf(parent_idx) && self.get_leaf_idx(parent_idx) == node_idx {
+            return chars
+                .next()
+                .is_none()
+                .then(|| (key, self.get_value(node_idx)));
+        }
+
+        // The rest of the prefix must be a prefix of the tail.
+        let tail_pos = usize::try_from(self.get_value(node_idx)).unwrap();
+        let mut tail_iter = self.tail_iter(tail_pos);
+        for c in chars {
+            let mc = self.mapper.get(c);
+            tail_iter.next().filter(|&tc| Some(tc) == mc)?;
+            key.push(c);
+        }
+        key.extend(
+            tail_iter
+                .by_ref()
+                .map(|tc| decoder[usize::try_from(tc).unwrap()]),
+        );
+        Some((key, tail_iter.value()))
+    }
+
+    /// Returns the child with the smallest or largest label in the lexicographical order,
+    /// where the child with [`END_CODE`] is the smallest.
+    fn extreme_child(
+        &self,
+        node_idx: u32,
+        decoder: &[char],
+        largest: bool,
+    ) -> Option<(bool, char, u32)> {
+        let children = (0..self.mapper.alphabet_size()).filter_map(|code| {
+            self.get_child_idx(node_idx, code).map(|child_idx| {
+                (
+                    code != END_CODE,
+                    decoder[usize::try_from(code).unwrap()],
+                    child_idx,
+                )
+            })
+        });
+        if largest {
+            children.max()
+        } else {
+            children.min()
+        }
+    }
+
     #[inline(always)]
     fn get_child_idx(&self, node_idx: u32, mc: u32) -> Option<u32> {
         if self.is_leaf(node_idx) {
@@ -327,6 +888,26 @@ impl MpTrie {
         self.node_ref(node_idx).get_base()
     }
 
+    /// Touches all the memory regions of the data structure to pre-fault their pages.
+    ///
+    /// This is useful after loading the data structure from a memory-mapped file,
+    /// trading startup time for lower latency of the first queries.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::MpTrie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = MpTrie::from_keys(&keys).unwrap();
+    /// trie.prewarm();
+    /// ```
+    pub fn prewarm(&self) {
+        self.mapper.prewarm();
+        utils::touch_pages(&self.nodes);
+        utils::touch_pages(&self.tails);
+    }
+
     /// Returns the total amount of heap used by this automaton in bytes.
     pub fn heap_bytes(&self) -> usize {
         self.mapper.heap_bytes()
@@ -335,8 +916,12 @@ impl MpTrie {
     }
 
     /// Returns the total amount of bytes to serialize the data structure.
+    ///
+    /// It is computed without serialization and equals the length of the vector
+    /// returned by [`MpTrie::serialize_to_vec()`], so it can be used to preallocate a buffer.
     pub fn io_bytes(&self) -> usize {
-        self.mapper.io_bytes()
+        format::HEADER_BYTES
+            + self.mapper.io_bytes()
             + self.nodes.len() * Node::io_bytes()
             + mem::size_of::<u32>()
             + self.tails.len() * mem::size_of::<u8>()
@@ -357,6 +942,24 @@ impl MpTrie {
     pub fn num_vacants(&self) -> usize {
         self.nodes.iter().filter(|nd| nd.is_vacant()).count()
     }
+
+    /// Returns the number of leaf nodes.
+    ///
+    /// # Note
+    ///
+    /// It takes `O(num_elems)` time.
+    pub fn num_leaves(&self) -> usize {
+        self.nodes.iter().filter(|nd| nd.is_leaf()).count()
+    }
+
+    /// Returns the number of internal nodes having a leaf for a key ending at them.
+    ///
+    /// # Note
+    ///
+    /// It takes `O(num_elems)` time.
+    pub fn num_has_leaf(&self) -> usize {
+        self.nodes.iter().filter(|nd| nd.has_leaf()).count()
+    }
 }
 
 /// Iterator for common prefix search.
@@ -417,7 +1020,7 @@ impl Iterator for TailIter<'_> {
     fn next(&mut self) -> Option<Self::Item> {
         if self.len != 0 {
             let c = utils::unpack_u32(&self.trie.tails[self.pos..], self.trie.code_size);
-            self.pos += usize::try_from(self.trie.code_size).unwrap();
+            self.pos += usize::from(self.trie.code_size);
             self.len -= 1;
             Some(c)
         } else {
@@ -426,10 +1029,99 @@ impl Iterator for TailIter<'_> {
     }
 }
 
+impl<'t> IntoIterator for &'t MpTrie {
+    type Item = (String, u32);
+    type IntoIter = Iter<'t>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Adds records to the trie, replacing the values of existing keys.
+///
+/// The trie is rebuilt from its records merged with the new ones,
+/// where the last value given for a key wins.
+/// It is rebuilt with the options of the [`Builder`] that built the trie,
+/// e.g., [`Builder::minimal_prefix_len()`] and [`Builder::identity_mapping()`],
+/// except [`Builder::value_offset()`] applying only to input keys.
+/// Since the options are not serialized, a deserialized trie is rebuilt with the default options.
+/// It takes `O((n + m) log (n + m))` time in addition to the construction,
+/// where `n` and `m` are the numbers of existing and new records,
+/// so adding records in a few large batches is much faster than in many small ones.
+/// Nothing is rebuilt for an empty batch.
+///
+/// # Panics
+///
+/// It panics when the construction fails (see [`MpTrie::from_records()`]),
+/// e.g., when a new key is empty.
+///
+/// # Examples
+///
+/// ```
+/// use crawdad::MpTrie;
+///
+/// let mut trie = MpTrie::from_records([("世界", 0), ("国民", 1)]).unwrap();
+/// trie.extend([("世界中", 2), ("国民", 3)]);
+///
+/// assert_eq!(trie.exact_match("世界".chars()), Some(0));
+/// assert_eq!(trie.exact_match("世界中".chars()), Some(2));
+/// assert_eq!(trie.exact_match("国民".chars()), Some(3));
+/// ```
+impl<K> Extend<(K, u32)> for MpTrie
+where
+    K: AsRef<str>,
+{
+    fn extend<T>(&mut self, records: T)
+    where
+        T: IntoIterator<Item = (K, u32)>,
+    {
+        let mut records = records.into_iter().peekable();
+        if records.peek().is_none() {
+            return;
+        }
+        let mut merged: BTreeMap<String, u32> = self.iter().collect();
+        merged.extend(records.map(|(key, value)| (key.as_ref().to_string(), value)));
+        *self = self
+            .options
+            .to_builder(true)
+            .build_from_records(merged)
+            .and_then(Builder::release_mptrie)
+            .expect("failed to rebuild the trie.");
+    }
+}
+
+/// Iterator over keys and their associated values, created by [`MpTrie::iter()`].
+pub struct Iter<'t> {
+    trie: &'t MpTrie,
+    leaves: LeafIter<'t>,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = (String, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let leaf_idx = self.leaves.next_leaf()?;
+        let mut key: String = self.leaves.key().iter().collect();
+        let parent_idx = self.trie.get_check(leaf_idx);
+        if self.trie.has_leaf(parent_idx) && self.trie.get_leaf_idx(parent_idx) == leaf_idx {
+            return Some((key, self.trie.get_value(leaf_idx)));
+        }
+        let tail_pos = usize::try_from(self.trie.get_value(leaf_idx)).unwrap();
+        let mut tail_iter = self.trie.tail_iter(tail_pos);
+        for code in tail_iter.by_ref() {
+            key.push(self.leaves.decode(code));
+        }
+        Some((key, tail_iter.value()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use alloc::string::ToString;
+
     #[test]
     fn test_exact_match() {
         let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
@@ -448,6 +1140,23 @@ mod tests {
         assert_eq!(trie.exact_match("日本".chars()), None);
     }
 
+    #[test]
+    fn test_query_end_marker() {
+        let trie = MpTrie::from_records([("世界", 1000), ("世界中", 1)]).unwrap();
+        assert_eq!(trie.exact_match("世界\u{ffff}".chars()), None);
+        assert_eq!(trie.exact_match("世界\u{ffff}中".chars()), None);
+        assert_eq!(trie.exact_match("\u{ffff}".chars()), None);
+        assert_eq!(
+            trie.common_prefix_search("世界\u{ffff}中".chars())
+                .collect::<Vec<_>>(),
+            vec![(1000, 2)]
+        );
+        assert_eq!(trie.common_prefix_count("世界\u{ffff}".chars()), 1);
+        assert_eq!(trie.shortest_prefix_match("\u{ffff}".chars()), None);
+        assert_eq!(trie.next_chars("世界\u{ffff}".chars()), vec![]);
+        assert_eq!(trie.min_key_with_prefix("世界\u{ffff}".chars()), None);
+    }
+
     #[test]
     fn test_common_prefix_search() {
         let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
@@ -464,6 +1173,331 @@ mod tests {
         assert_eq!(matches, vec![(0, 0..2), (1, 0..3), (2, 6..10)]);
     }
 
+    #[test]
+    fn test_matches_are_end_sorted() {
+        let keys = vec!["世", "世界", "世界中", "世界中で", "世論", "世論調査"];
+        let trie = MpTrie::from_keys(&keys).unwrap();
+
+        for haystack in ["世界中で", "世界中の", "世論調査", "世論調", "世"] {
+            let ends: Vec<_> = trie
+                .common_prefix_search(haystack.chars())
+                .map(|(_, j)| j)
+                .collect();
+            assert!(!ends.is_empty());
+            assert!(ends.windows(2).all(|w| w[0] < w[1]));
+        }
+    }
+
+    #[test]
+    fn test_prewarm() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = MpTrie::from_keys(&keys).unwrap();
+        trie.prewarm();
+    }
+
+    #[test]
+    fn test_iter() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = MpTrie::from_keys(&keys).unwrap();
+        let expected: Vec<_> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, k)| (k.to_string(), u32::try_from(i).unwrap()))
+            .collect();
+        assert_eq!(trie.iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut trie = MpTrie::from_records([("世界", 0), ("世論調査", 2), ("統計", 5)]).unwrap();
+        trie.extend([("世界中", 1), ("世", 3), ("統計", 4)]);
+        let expected = vec![
+            ("世".to_string(), 3),
+            ("世界".to_string(), 0),
+            ("世界中".to_string(), 1),
+            ("世論調査".to_string(), 2),
+            ("統計".to_string(), 4),
+        ];
+        assert_eq!(trie.iter().collect::<Vec<_>>(), expected);
+        for (key, value) in &expected {
+            assert_eq!(trie.exact_match(key.chars()), Some(*value));
+        }
+    }
+
+    #[test]
+    fn test_extend_options() {
+        let records = [("世界", 0), ("世界中", 1), ("世論調査", 2), ("統計", 3)];
+        let builder = Builder::new().minimal_prefix_len(2).identity_mapping();
+        let mut trie = builder
+            .clone()
+            .build_from_records(records[..2].iter().copied())
+            .unwrap()
+            .release_mptrie()
+            .unwrap();
+        trie.extend(records[2..].iter().copied());
+        let expected = builder
+            .build_from_records(records)
+            .unwrap()
+            .release_mptrie()
+            .unwrap();
+        assert_eq!(trie.serialize_to_vec(), expected.serialize_to_vec());
+
+        // A deserialized trie is rebuilt with the default options.
+        let bytes = trie.serialize_to_vec();
+        let (mut other, _) = MpTrie::deserialize_from_slice(&bytes);
+        other.extend([("国民", 4)]);
+        assert_eq!(other.options, BuildOptions::default());
+    }
+
+    #[test]
+    fn test_iter_same_as_trie() {
+        let records = vec![
+            ("世界", 3),
+            ("世界中", 1),
+            ("世論調査", 4),
+            ("統計調査", 1),
+            ("世", 5),
+            ("世論", 9),
+            ("統計調査員", 2),
+            ("a", 7),
+            ("abc", 0),
+            ("b", 8),
+        ];
+        let expected: Vec<_> = crate::Trie::from_records(records.iter().copied())
+            .unwrap()
+            .iter()
+            .collect();
+        for min in 0..4 {
+            let trie = Builder::new()
+                .minimal_prefix_len(min)
+                .build_from_records(records.iter().copied())
+                .unwrap()
+                .release_mptrie()
+                .unwrap();
+            assert_eq!(trie.iter().collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[test]
+    fn test_iter_unsorted_records() {
+        let records = vec![
+            ("b", 3),
+            ("a\0", 1),
+            ("ab", 4),
+            ("a", 1),
+            ("abc", 5),
+            ("\0", 9),
+        ];
+        let trie = MpTrie::from_records(records.clone()).unwrap();
+        let mut expected: Vec<_> = records.iter().map(|&(k, v)| (k.to_string(), v)).collect();
+        expected.sort_unstable();
+        assert_eq!(trie.iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_shortest_prefix_match() {
+        let keys = vec!["世", "世界", "世界中", "世論調査", "統計調査"];
+        let trie = MpTrie::from_keys(&keys).unwrap();
+        assert_eq!(trie.shortest_prefix_match("世界中で".chars()), Some((0, 1)));
+        assert_eq!(trie.shortest_prefix_match("世論調査".chars()), Some((0, 1)));
+        assert_eq!(
+            trie.shortest_prefix_match("統計調査で".chars()),
+            Some((4, 4))
+        );
+        assert_eq!(trie.shortest_prefix_match("統計".chars()), None);
+        assert_eq!(trie.shortest_prefix_match("日本".chars()), None);
+    }
+
+    #[test]
+    fn test_common_prefix_count() {
+        let keys = vec!["世", "世界", "世界中", "世論調査", "統計調査"];
+        let trie = MpTrie::from_keys(&keys).unwrap();
+        for haystack in [
+            "世界中で",
+            "世論調査",
+            "世論調",
+            "統計調査で",
+            "統計",
+            "日本",
+            "",
+        ] {
+            assert_eq!(
+                trie.common_prefix_count(haystack.chars()),
+                trie.common_prefix_search(haystack.chars()).count()
+            );
+        }
+        assert_eq!(trie.common_prefix_count("世界中で".chars()), 3);
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = MpTrie::from_keys(&keys).unwrap();
+        let mut records = vec![];
+        for (key, value) in &trie {
+            records.push((key, value));
+        }
+        let expected: Vec<_> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, k)| (k.to_string(), u32::try_from(i).unwrap()))
+            .collect();
+        assert_eq!(records, expected);
+    }
+
+    #[test]
+    fn test_for_each_common_prefix() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = MpTrie::from_keys(&keys).unwrap();
+
+        let mut matches = vec![];
+        trie.for_each_common_prefix("世界中国".chars(), |v, j| {
+            matches.push((v, j));
+            ControlFlow::Continue(())
+        });
+        assert_eq!(matches, vec![(0, 2), (1, 3)]);
+
+        let mut num_calls = 0;
+        trie.for_each_common_prefix("世界中国".chars(), |_, _| {
+            num_calls += 1;
+            ControlFlow::Break(())
+        });
+        assert_eq!(num_calls, 1);
+    }
+
+    #[test]
+    fn test_next_chars() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = MpTrie::from_keys(&keys).unwrap();
+
+        assert_eq!(trie.next_chars("".chars()), vec!['世', '統']);
+        assert_eq!(trie.next_chars("世".chars()), vec!['界', '論']);
+        assert_eq!(trie.next_chars("世界".chars()), vec!['中']);
+        // "調査" is in the tail.
+        assert_eq!(trie.next_chars("世論".chars()), vec!['調']);
+        assert_eq!(trie.next_chars("世論調".chars()), vec!['査']);
+        assert!(trie.next_chars("世論調査".chars()).is_empty());
+        assert!(trie.next_chars("世論調査中".chars()).is_empty());
+        assert!(trie.next_chars("世論国".chars()).is_empty());
+        assert!(trie.next_chars("世界中".chars()).is_empty());
+        assert!(trie.next_chars("日本".chars()).is_empty());
+    }
+
+    #[test]
+    fn test_exact_match_with_len() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査", "a", "ab"];
+        let trie = MpTrie::from_keys(&keys).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(
+                trie.exact_match_with_len(key.chars()),
+                Some((u32::try_from(i).unwrap(), key.chars().count()))
+            );
+        }
+        assert_eq!(trie.exact_match_with_len("世論".chars()), None);
+        assert_eq!(trie.exact_match_with_len("世論調査中".chars()), None);
+    }
+
+    #[test]
+    fn test_extreme_key_with_prefix() {
+        let keys = vec![
+            "世界",
+            "世界中",
+            "世論調査",
+            "統計調査",
+            "世",
+            "世論",
+            "統計",
+            "統計学",
+            "a",
+            "ab",
+            "abc",
+        ];
+        let trie = MpTrie::from_keys(&keys).unwrap();
+        let records: Vec<_> = trie.iter().collect();
+        for prefix in [
+            "",
+            "世",
+            "世界",
+            "世界中",
+            "世論",
+            "世論調",
+            "統",
+            "統計",
+            "a",
+            "ab",
+            "abc",
+            "x",
+            "世論調査中",
+        ] {
+            let under = || {
+                records
+                    .iter()
+                    .filter(|(k, _)| k.starts_with(prefix))
+                    .cloned()
+            };
+            assert_eq!(trie.min_key_with_prefix(prefix.chars()), under().min());
+            assert_eq!(trie.max_key_with_prefix(prefix.chars()), under().max());
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = MpTrie::from_keys(&keys).unwrap();
+        assert!(trie.validate().is_ok());
+
+        let corrupt = |f: &dyn Fn(&mut MpTrie)| {
+            let mut other = MpTrie::deserialize_from_slice(&trie.serialize_to_vec()).0;
+            f(&mut other);
+            other.validate()
+        };
+        let len = u32::try_from(trie.nodes.len()).unwrap();
+
+        // Points to a parent out of bounds.
+        assert!(corrupt(&|t| t.nodes[1].check = len).is_err());
+        // Truncates the tails.
+        assert!(corrupt(&|t| t.tails.truncate(t.tails.len() - 1)).is_err());
+        // Breaks the code of the tail "調査" in "世論調査".
+        assert!(corrupt(&|t| t.tails[1] = 0).is_err());
+        // Breaks the packing size.
+        assert!(corrupt(&|t| t.value_size = 0).is_err());
+    }
+
+    #[test]
+    fn test_minimal_prefix_len() {
+        let keys = vec!["世", "世界", "世界中", "世論調査", "統計調査", "統計調査員"];
+        let mut prev_num_elems = 0;
+        for min in 0..7 {
+            let trie = Builder::new()
+                .minimal_prefix_len(min)
+                .build_from_keys(&keys)
+                .unwrap()
+                .release_mptrie()
+                .unwrap();
+            for (i, key) in keys.iter().enumerate() {
+                assert_eq!(
+                    trie.exact_match(key.chars()),
+                    Some(u32::try_from(i).unwrap())
+                );
+            }
+            assert_eq!(trie.exact_match("世論".chars()), None);
+            assert_eq!(trie.exact_match("統計調査員で".chars()), None);
+            let matches: Vec<_> = trie.common_prefix_search("統計調査員で".chars()).collect();
+            assert_eq!(matches, vec![(4, 4), (5, 5)]);
+            assert_eq!(trie.iter().count(), keys.len());
+            assert!(prev_num_elems <= trie.num_elems() - trie.num_vacants());
+            prev_num_elems = trie.num_elems() - trie.num_vacants();
+        }
+    }
+
+    #[test]
+    fn test_num_leaves() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = MpTrie::from_keys(&keys).unwrap();
+        assert_eq!(trie.num_leaves(), 4);
+        assert_eq!(trie.num_has_leaf(), 1);
+    }
+
     #[test]
     fn test_serialize() {
         let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
@@ -482,6 +1516,90 @@ mod tests {
         assert_eq!(trie.value_size, other.value_size);
     }
 
+    #[test]
+    fn test_try_deserialize() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = MpTrie::from_keys(&keys).unwrap();
+
+        let mut bytes = trie.serialize_to_vec();
+        bytes.push(42);
+        let (other, remain) = MpTrie::try_deserialize_from_slice(&bytes).unwrap();
+        assert_eq!(remain, &[42]);
+        assert_eq!(trie.mapper, other.mapper);
+        assert_eq!(trie.nodes, other.nodes);
+        assert_eq!(trie.tails, other.tails);
+
+        for len in 0..trie.io_bytes() {
+            assert!(MpTrie::try_deserialize_from_slice(&bytes[..len]).is_err());
+        }
+
+        // Flips bits of the bytes following the code mapper.
+        let beg = format::HEADER_BYTES + trie.mapper.io_bytes();
+        for i in beg..bytes.len() {
+            let mut broken = bytes.clone();
+            broken[i] ^= 0x81;
+            if let Ok((other, _)) = MpTrie::try_deserialize_from_slice(&broken) {
+                for key in &keys {
+                    other.exact_match(key.chars());
+                    other.common_prefix_search(key.chars()).count();
+                }
+                other.iter().count();
+            }
+        }
+    }
+
+    #[test]
+    fn test_migrate() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = MpTrie::from_keys(&keys).unwrap();
+        let bytes = trie.serialize_to_vec();
+
+        // The format of version 1 is the current one without the header.
+        let legacy = &bytes[format::HEADER_BYTES..];
+        let e = MpTrie::try_deserialize_from_slice(legacy).err().unwrap();
+        assert!(matches!(
+            e,
+            crate::errors::CrawdadError::UnsupportedVersion(ref e)
+                if e.found() == format::LEGACY_VERSION && e.supported() == format::FORMAT_VERSION
+        ));
+        let (other, remain) = MpTrie::migrate_from_slice(legacy).unwrap();
+        assert!(remain.is_empty());
+        assert_eq!(other.serialize_to_vec(), bytes);
+
+        let (other, remain) = MpTrie::migrate_from_slice(&bytes).unwrap();
+        assert!(remain.is_empty());
+        assert_eq!(other.serialize_to_vec(), bytes);
+
+        // A future version is rejected even by the migration.
+        let mut future = bytes.clone();
+        future[4..8].copy_from_slice(&(format::FORMAT_VERSION + 1).to_le_bytes());
+        for result in [
+            MpTrie::try_deserialize_from_slice(&future),
+            MpTrie::migrate_from_slice(&future),
+        ] {
+            let e = result.err().unwrap();
+            assert!(matches!(
+                e,
+                crate::errors::CrawdadError::UnsupportedVersion(ref e)
+                    if e.found() == format::FORMAT_VERSION + 1
+            ));
+        }
+    }
+
+    #[test]
+    fn test_io_bytes() {
+        let key_sets: [&[&str]; 4] = [
+            &["世界", "国民"],
+            &["a", "ab", "abc"],
+            &["世界", "世界中", "世論調査", "統計調査"],
+            &["\0", "\u{10ffff}", "🦞", "crawdad"],
+        ];
+        for keys in key_sets {
+            let trie = MpTrie::from_keys(keys).unwrap();
+            assert_eq!(trie.io_bytes(), trie.serialize_to_vec().len());
+        }
+    }
+
     #[test]
     fn test_empty_set() {
         assert!(MpTrie::from_keys(&[""][0..0]).is_err());
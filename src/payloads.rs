@@ -0,0 +1,159 @@
+use alloc::vec::Vec;
+
+use core::mem::size_of;
+
+use crate::errors::{CrawdadError, Result};
+use crate::utils;
+
+/// Byte payloads indexed by the values of keys.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct PayloadStore {
+    // Concatenation of payloads.
+    blob: Vec<u8>,
+    // The i-th payload is blob[offsets[i]..offsets[i + 1]].
+    offsets: Vec<u32>,
+}
+
+impl PayloadStore {
+    /// Creates a store from payloads in the order of their indices.
+    pub fn new<I, P>(payloads: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        let mut blob = vec![];
+        let mut offsets = vec![0];
+        for payload in payloads {
+            blob.extend_from_slice(payload.as_ref());
+            let offset = u32::try_from(blob.len())
+                .map_err(|_| CrawdadError::scale("length of payloads", u32::MAX))?;
+            offsets.push(offset);
+        }
+        Ok(Self { blob, offsets })
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// Returns the payload of an index if exists.
+    #[inline(always)]
+    pub fn get(&self, i: usize) -> Option<&[u8]> {
+        if self.len() <= i {
+            return None;
+        }
+        let beg = usize::try_from(self.offsets[i]).unwrap();
+        let end = usize::try_from(self.offsets[i + 1]).unwrap();
+        Some(&self.blob[beg..end])
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        self.blob.len() + self.offsets.len() * size_of::<u32>()
+    }
+
+    /// Returns the number of bytes of optional payloads serialized by
+    /// [`PayloadStore::serialize_into_vec()`].
+    pub fn io_bytes(this: Option<&Self>) -> usize {
+        this.map_or(0, |store| store.heap_bytes() + size_of::<u32>()) + size_of::<u32>()
+    }
+
+    /// Serializes optional payloads, where [`None`] is written as zero offsets
+    /// to be distinguished from a store of no payload having an offset.
+    pub fn serialize_into_vec(this: Option<&Self>, dest: &mut Vec<u8>) {
+        let store = match this {
+            Some(store) => store,
+            None => {
+                dest.extend_from_slice(&0u32.to_le_bytes());
+                return;
+            }
+        };
+        dest.extend_from_slice(&u32::try_from(store.offsets.len()).unwrap().to_le_bytes());
+        for x in &store.offsets {
+            dest.extend_from_slice(&x.to_le_bytes());
+        }
+        dest.extend_from_slice(&u32::try_from(store.blob.len()).unwrap().to_le_bytes());
+        dest.extend_from_slice(&store.blob);
+    }
+
+    /// Deserializes optional payloads written by [`PayloadStore::serialize_into_vec()`],
+    /// or returns an error if `source` is too short or the offsets are broken.
+    pub fn try_deserialize_from_slice(mut source: &[u8]) -> Result<(Option<Self>, &[u8])> {
+        let len = utils::split_u32(&mut source)?;
+        if len == 0 {
+            return Ok((None, source));
+        }
+        let offsets: Vec<u32> = utils::split_array(&mut source, len, size_of::<u32>())?
+            .chunks_exact(size_of::<u32>())
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        let blob_len = utils::split_u32(&mut source)?;
+        let blob = utils::split_array(&mut source, blob_len, 1)?.to_vec();
+        if offsets[0] != 0
+            || offsets.windows(2).any(|w| w[1] < w[0])
+            || offsets[offsets.len() - 1] != blob_len
+        {
+            return Err(CrawdadError::corrupted("payload offsets are broken."));
+        }
+        Ok((Some(Self { blob, offsets }), source))
+    }
+
+    /// Deserializes optional payloads like [`PayloadStore::try_deserialize_from_slice()`],
+    /// but panics on error.
+    pub fn deserialize_from_slice(source: &[u8]) -> (Option<Self>, &[u8]) {
+        Self::try_deserialize_from_slice(source).expect("payloads are broken.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get() {
+        let store = PayloadStore::new(["せかい", "", "こくみん"]).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get(0), Some("せかい".as_bytes()));
+        assert_eq!(store.get(1), Some(&[][..]));
+        assert_eq!(store.get(2), Some("こくみん".as_bytes()));
+        assert_eq!(store.get(3), None);
+
+        let store = PayloadStore::new(Vec::<&[u8]>::new()).unwrap();
+        assert_eq!(store.len(), 0);
+        assert_eq!(store.get(0), None);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let stores = [
+            None,
+            Some(PayloadStore::new(Vec::<&[u8]>::new()).unwrap()),
+            Some(PayloadStore::new(["せかい", "", "こくみん"]).unwrap()),
+        ];
+        for store in &stores {
+            let mut bytes = vec![];
+            PayloadStore::serialize_into_vec(store.as_ref(), &mut bytes);
+            assert_eq!(bytes.len(), PayloadStore::io_bytes(store.as_ref()));
+            bytes.push(42);
+            let (other, rest) = PayloadStore::try_deserialize_from_slice(&bytes).unwrap();
+            assert_eq!(&other, store);
+            assert_eq!(rest, &[42]);
+            for i in 0..bytes.len() - 1 {
+                assert!(PayloadStore::try_deserialize_from_slice(&bytes[..i]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_broken_offsets() {
+        let store = PayloadStore::new(["せかい", "こくみん"]).unwrap();
+        let mut bytes = vec![];
+        PayloadStore::serialize_into_vec(Some(&store), &mut bytes);
+        // Swaps the offsets of the first and second payloads.
+        bytes[8..16].copy_from_slice(&[21, 0, 0, 0, 9, 0, 0, 0]);
+        assert!(matches!(
+            PayloadStore::try_deserialize_from_slice(&bytes),
+            Err(CrawdadError::Corrupted(_))
+        ));
+    }
+}
@@ -0,0 +1,120 @@
+use alloc::vec::Vec;
+
+use crate::mapper::CodeMapper;
+use crate::{Node, END_CODE};
+
+/// Children of every node sorted in the lexicographical order of their labels,
+/// where the child labeled with [`END_CODE`] comes first.
+pub struct ChildTable {
+    offsets: Vec<u32>,
+    children: Vec<u32>,
+}
+
+impl ChildTable {
+    pub fn new(nodes: &[Node], decoder: &[char]) -> Self {
+        let mut edges = Vec::with_capacity(nodes.len());
+        for (i, node) in nodes.iter().enumerate().skip(1) {
+            if node.is_vacant() {
+                continue;
+            }
+            let child_idx = u32::try_from(i).unwrap();
+            let parent_idx = node.get_check();
+            let code = nodes[usize::try_from(parent_idx).unwrap()].get_base() ^ child_idx;
+            let label = usize::try_from(code).unwrap();
+            edges.push((parent_idx, code != END_CODE, decoder[label], child_idx));
+        }
+        edges.sort_unstable();
+
+        let mut offsets = vec![0; nodes.len() + 1];
+        for &(parent_idx, ..) in &edges {
+            offsets[usize::try_from(parent_idx).unwrap() + 1] += 1;
+        }
+        for i in 1..offsets.len() {
+            offsets[i] += offsets[i - 1];
+        }
+        let children = edges.into_iter().map(|(.., child_idx)| child_idx).collect();
+        Self { offsets, children }
+    }
+
+    #[inline(always)]
+    pub fn children(&self, node_idx: u32) -> &[u32] {
+        let i = usize::try_from(node_idx).unwrap();
+        let beg = usize::try_from(self.offsets[i]).unwrap();
+        let end = usize::try_from(self.offsets[i + 1]).unwrap();
+        &self.children[beg..end]
+    }
+}
+
+/// Depth-first traversal visiting leaves in the lexicographical order of their keys,
+/// or in the reverse order.
+pub struct LeafIter<'a> {
+    nodes: &'a [Node],
+    decoder: Vec<char>,
+    table: ChildTable,
+    stack: Vec<(u32, usize)>,
+    key: Vec<char>,
+    reverse: bool,
+}
+
+impl<'a> LeafIter<'a> {
+    pub fn new(mapper: &CodeMapper, nodes: &'a [Node]) -> Self {
+        let decoder = mapper.decoder();
+        let table = ChildTable::new(nodes, &decoder);
+        Self {
+            nodes,
+            decoder,
+            table,
+            stack: vec![(0, 0)],
+            key: vec![],
+            reverse: false,
+        }
+    }
+
+    /// Creates a traversal visiting leaves in the reverse lexicographical order.
+    pub fn new_rev(mapper: &CodeMapper, nodes: &'a [Node]) -> Self {
+        Self {
+            reverse: true,
+            ..Self::new(mapper, nodes)
+        }
+    }
+
+    /// Returns the key of the last visited leaf, excluding the terminator.
+    #[inline(always)]
+    pub fn key(&self) -> &[char] {
+        &self.key
+    }
+
+    #[inline(always)]
+    pub fn decode(&self, code: u32) -> char {
+        self.decoder[usize::try_from(code).unwrap()]
+    }
+
+    /// Returns the index of the next leaf.
+    pub fn next_leaf(&mut self) -> Option<u32> {
+        while let Some((node_idx, depth)) = self.stack.pop() {
+            self.key.truncate(depth);
+            let node = &self.nodes[usize::try_from(node_idx).unwrap()];
+            if node_idx != 0 {
+                let parent = &self.nodes[usize::try_from(node.get_check()).unwrap()];
+                let code = parent.get_base() ^ node_idx;
+                if code != END_CODE {
+                    self.key.push(self.decoder[usize::try_from(code).unwrap()]);
+                }
+            }
+            if node.is_leaf() {
+                return Some(node_idx);
+            }
+            let depth = self.key.len();
+            let children = self.table.children(node_idx);
+            if self.reverse {
+                // The child labeled with END_CODE is visited last.
+                self.stack
+                    .extend(children.iter().map(|&child_idx| (child_idx, depth)));
+            } else {
+                self.stack
+                    .extend(children.iter().rev().map(|&child_idx| (child_idx, depth)));
+            }
+        }
+        None
+    }
+}
@@ -1,30 +1,49 @@
 //! A standard trie form that often provides the fastest queries.
-use crate::builder::Builder;
+use crate::builder::{BuildOptions, Builder};
+use crate::diff::{self, Merged};
 use crate::errors::Result;
+use crate::keys::KeyStore;
 use crate::mapper::CodeMapper;
-use crate::Node;
+use crate::payloads::PayloadStore;
+use crate::traverse::{ChildTable, LeafIter};
+use crate::{format, utils, validate, Node};
 
-use crate::END_CODE;
+use crate::{END_CODE, MAX_VALUE, OFFSET_MASK};
 
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
+use core::iter::Copied;
 use core::mem;
+use core::ops::{ControlFlow, Range};
+use core::slice;
+use core::str::Chars;
 
 /// A standard trie form that often provides the fastest queries.
 pub struct Trie {
     pub(crate) mapper: CodeMapper,
     pub(crate) nodes: Vec<Node>,
+    pub(crate) keys: Option<KeyStore>,
+    pub(crate) payloads: Option<PayloadStore>,
+    pub(crate) options: BuildOptions,
 }
 
 impl Trie {
+    /// State of the root, from which [`Trie::transition()`] starts.
+    pub const ROOT: u32 = 0;
+
     /// Creates a new [`Trie`] from input keys.
     ///
-    /// Values in `[0..n-1]` will be associated with keys in the lexicographical order,
+    /// Values in `[0..n-1]` will be associated with keys in the input order,
     /// where `n` is the number of keys.
+    /// Keys can be given in any order; they are sorted by the builder.
+    /// If they are sorted by the [`Ord`] of [`str`] (e.g., with [`prepare_keys()`](crate::prepare_keys)),
+    /// the values follow the lexicographical order.
     ///
     /// # Arguments
     ///
-    /// - `keys`: Sorted list of string keys.
+    /// - `keys`: List of string keys.
     ///
     /// # Errors
     ///
@@ -54,6 +73,32 @@ impl Trie {
         Builder::new().build_from_keys(keys)?.release_trie()
     }
 
+    /// Creates a new [`Trie`] from a slice of string keys.
+    ///
+    /// It works as [`Trie::from_keys()`] with a concrete argument type,
+    /// for the common case where generic bounds only obscure compile errors.
+    ///
+    /// # Arguments
+    ///
+    /// - `keys`: Slice of string keys.
+    ///
+    /// # Errors
+    ///
+    /// See [`Trie::from_keys()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let trie = Trie::from_str_slice(&["世界", "世界中", "国民"]).unwrap();
+    ///
+    /// assert_eq!(trie.exact_match("国民".chars()), Some(2));
+    /// ```
+    pub fn from_str_slice(keys: &[&str]) -> Result<Self> {
+        Self::from_keys(keys)
+    }
+
     /// Creates a new [`Trie`] from input records.
     ///
     /// # Arguments
@@ -90,6 +135,11 @@ impl Trie {
 
     /// Serializes the data structure into a [`Vec`].
     ///
+    /// The bytes start with a header of the format version,
+    /// which is checked by the deserialization.
+    /// Payloads attached by [`Builder::build_from_payloads()`](crate::Builder::build_from_payloads)
+    /// are serialized, but keys kept by [`Builder::keep_keys()`](crate::Builder::keep_keys) are not.
+    ///
     /// # Examples
     ///
     /// ```
@@ -101,11 +151,13 @@ impl Trie {
     /// ```
     pub fn serialize_to_vec(&self) -> Vec<u8> {
         let mut dest = Vec::with_capacity(self.io_bytes());
+        format::serialize_header(&mut dest);
         self.mapper.serialize_into_vec(&mut dest);
         dest.extend_from_slice(&u32::try_from(self.nodes.len()).unwrap().to_le_bytes());
         for node in &self.nodes {
             dest.extend_from_slice(&node.serialize());
         }
+        PayloadStore::serialize_into_vec(self.payloads.as_ref(), &mut dest);
         dest
     }
 
@@ -119,6 +171,13 @@ impl Trie {
     ///
     /// A tuple of the data structure and the slice not used for the deserialization.
     ///
+    /// # Panics
+    ///
+    /// It panics if the format version of `source` is not supported,
+    /// or might panic if `source` is broken.
+    /// Use [`Trie::try_deserialize_from_slice()`] for untrusted bytes
+    /// and [`Trie::migrate_from_slice()`] for bytes of an older format.
+    ///
     /// # Examples
     ///
     /// ```
@@ -133,6 +192,7 @@ impl Trie {
     /// assert_eq!(trie.io_bytes(), other.io_bytes());
     /// ```
     pub fn deserialize_from_slice(source: &[u8]) -> (Self, &[u8]) {
+        let source = format::skip_header(source);
         let (mapper, mut source) = CodeMapper::deserialize_from_slice(source);
         let nodes = {
             let len = u32::from_le_bytes(source[..4].try_into().unwrap()) as usize;
@@ -146,14 +206,42 @@ impl Trie {
             }
             nodes
         };
-        (Self { mapper, nodes }, source)
+        let (payloads, source) = PayloadStore::deserialize_from_slice(source);
+        (
+            Self {
+                mapper,
+                nodes,
+                keys: None,
+                payloads,
+                options: BuildOptions::default(),
+            },
+            source,
+        )
     }
 
-    /// Returns a value associated with an input key if exists.
+    /// Deserializes the data structure from a given byte slice
+    /// while checking that it is not broken.
+    ///
+    /// Unlike [`Trie::deserialize_from_slice()`], this never panics on malformed bytes,
+    /// and the resulting trie passes [`Trie::validate()`].
+    /// It is suitable for loading bytes from untrusted sources.
     ///
     /// # Arguments
     ///
-    /// - `key`: Search key.
+    /// * `source` - A source byte slice.
+    ///
+    /// # Returns
+    ///
+    /// A tuple of the data structure and the slice not used for the deserialization.
+    ///
+    /// # Errors
+    ///
+    /// [`CrawdadError`](crate::errors::CrawdadError) will be returned when
+    ///
+    /// - the format version of `source` is not supported
+    ///   ([`CrawdadError::UnsupportedVersion`](crate::errors::CrawdadError::UnsupportedVersion)), or
+    /// - `source` is too short or the deserialized data structure is broken
+    ///   ([`CrawdadError::Corrupted`](crate::errors::CrawdadError::Corrupted)).
     ///
     /// # Examples
     ///
@@ -163,179 +251,3461 @@ impl Trie {
     /// let keys = vec!["世界", "世界中", "国民"];
     /// let trie = Trie::from_keys(&keys).unwrap();
     ///
-    /// assert_eq!(trie.exact_match("世界中".chars()), Some(1));
-    /// assert_eq!(trie.exact_match("日本中".chars()), None);
+    /// let bytes = trie.serialize_to_vec();
+    /// let (other, _) = Trie::try_deserialize_from_slice(&bytes).unwrap();
+    /// assert_eq!(other.exact_match("世界中".chars()), Some(1));
+    ///
+    /// assert!(Trie::try_deserialize_from_slice(&bytes[..bytes.len() - 1]).is_err());
     /// ```
-    #[inline(always)]
-    pub fn exact_match<I>(&self, key: I) -> Option<u32>
-    where
-        I: IntoIterator<Item = char>,
-    {
-        let mut node_idx = 0;
-        for c in key {
-            node_idx = self
-                .mapper
-                .get(c)
-                .and_then(|mc| self.get_child_idx(node_idx, mc))?;
-        }
-        if self.is_leaf(node_idx) {
-            Some(self.get_value(node_idx))
-        } else if self.has_leaf(node_idx) {
-            Some(self.get_value(self.get_leaf_idx(node_idx)))
-        } else {
-            None
-        }
+    pub fn try_deserialize_from_slice(mut source: &[u8]) -> Result<(Self, &[u8])> {
+        format::split_header(&mut source)?;
+        Self::try_deserialize_body(source, format::FORMAT_VERSION)
     }
 
-    /// Returns an iterator for common prefix search.
+    /// Deserializes the data structure from a given byte slice in the current
+    /// or an older format, while checking that it is not broken.
     ///
-    /// The iterator reports all occurrences of keys starting from an input haystack, where
-    /// an occurrence consists of its associated value and ending positoin in characters.
+    /// It accepts the headerless format written by crawdad 0.4 and earlier
+    /// (format version 1) as well as the current one.
+    /// The resulting trie is serialized in the current format by [`Trie::serialize_to_vec()`],
+    /// so this upgrades bytes of an older format.
     ///
-    /// # Examples
+    /// # Arguments
     ///
-    /// You can find all occurrences of keys in a haystack by performing common prefix searches
-    /// at all starting positions.
+    /// * `source` - A source byte slice.
+    ///
+    /// # Returns
+    ///
+    /// A tuple of the data structure and the slice not used for the deserialization.
+    ///
+    /// # Errors
+    ///
+    /// See [`Trie::try_deserialize_from_slice()`].
+    ///
+    /// # Examples
     ///
     /// ```
     /// use crawdad::Trie;
     ///
     /// let keys = vec!["世界", "世界中", "国民"];
     /// let trie = Trie::from_keys(&keys).unwrap();
+    /// let bytes = trie.serialize_to_vec();
     ///
-    /// let haystack: Vec<char> = "国民が世界中にて".chars().collect();
-    /// let mut matches = vec![];
-    ///
-    /// for i in 0..haystack.len() {
-    ///     for (v, j) in trie.common_prefix_search(haystack[i..].iter().copied()) {
-    ///         matches.push((v, i..i + j));
-    ///     }
-    /// }
-    ///
-    /// assert_eq!(
-    ///     matches,
-    ///     vec![(2, 0..2), (0, 3..5), (1, 3..6)]
-    /// );
+    /// let (other, _) = Trie::migrate_from_slice(&bytes).unwrap();
+    /// assert_eq!(other.exact_match("世界中".chars()), Some(1));
     /// ```
-    pub const fn common_prefix_search<I>(&self, haystack: I) -> CommonPrefixSearchIter<I> {
-        CommonPrefixSearchIter {
-            haystack,
-            haystack_pos: 0,
-            trie: self,
-            node_idx: 0,
+    pub fn migrate_from_slice(mut source: &[u8]) -> Result<(Self, &[u8])> {
+        let version = format::version(source);
+        if version != format::LEGACY_VERSION {
+            format::split_header(&mut source)?;
         }
+        Self::try_deserialize_body(source, version)
     }
 
-    #[inline(always)]
-    fn get_child_idx(&self, node_idx: u32, mc: u32) -> Option<u32> {
-        if self.is_leaf(node_idx) {
-            return None;
-        }
-        Some(self.get_base(node_idx) ^ mc)
-            .filter(|&child_idx| self.get_check(child_idx) == node_idx)
+    fn try_deserialize_body(source: &[u8], version: u32) -> Result<(Self, &[u8])> {
+        let (mapper, mut source) = CodeMapper::try_deserialize_from_slice(source)?;
+        let len = utils::split_u32(&mut source)?;
+        let nodes = utils::split_array(&mut source, len, Node::io_bytes())?
+            .chunks_exact(Node::io_bytes())
+            .map(|bytes| Node::deserialize(bytes.try_into().unwrap()))
+            .collect();
+        // The legacy format has no payload.
+        let (payloads, source) = if version == format::LEGACY_VERSION {
+            (None, source)
+        } else {
+            PayloadStore::try_deserialize_from_slice(source)?
+        };
+        let trie = Self {
+            mapper,
+            nodes,
+            keys: None,
+            payloads,
+            options: BuildOptions::default(),
+        };
+        trie.validate()?;
+        Ok((trie, source))
     }
 
-    #[inline(always)]
-    fn node_ref(&self, node_idx: u32) -> &Node {
-        &self.nodes[usize::try_from(node_idx).unwrap()]
+    /// Returns the version of crawdad that produced serialized bytes, e.g., `"0.5.0"`,
+    /// reading only the header.
+    ///
+    /// The version is recorded as it is, including pre-release and build metadata
+    /// such as `"0.5.0-rc.1"`.
+    ///
+    /// [`None`] is returned if the bytes do not start with a header of the current format,
+    /// e.g., bytes written by crawdad 0.4 and earlier.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - A source byte slice.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let trie = Trie::from_keys(["世界", "世界中", "国民"]).unwrap();
+    /// let bytes = trie.serialize_to_vec();
+    ///
+    /// assert_eq!(Trie::producer_version(&bytes).unwrap(), env!("CARGO_PKG_VERSION"));
+    /// assert_eq!(Trie::producer_version(&bytes[4..]), None);
+    /// ```
+    pub fn producer_version(source: &[u8]) -> Option<String> {
+        format::producer_version(source)
     }
 
-    #[inline(always)]
-    fn get_base(&self, node_idx: u32) -> u32 {
-        self.node_ref(node_idx).get_base()
+    /// Checks the structural invariants of the data structure.
+    ///
+    /// This is useful to verify a trie deserialized from untrusted bytes
+    /// before querying it, since queries on a broken trie may panic.
+    ///
+    /// # Errors
+    ///
+    /// [`CrawdadError::Corrupted`](crate::errors::CrawdadError::Corrupted) will be returned
+    /// on the first violation found, e.g., when
+    ///
+    /// - `check` of a node does not point to a valid parent,
+    /// - `base` of a node points out of bounds,
+    /// - leaf and has-leaf flags are inconsistent, or
+    /// - some nodes are unreachable from the root.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// let bytes = trie.serialize_to_vec();
+    /// let (other, _) = Trie::deserialize_from_slice(&bytes);
+    /// assert!(other.validate().is_ok());
+    /// ```
+    pub fn validate(&self) -> Result<()> {
+        validate::validate_nodes(&self.mapper, &self.nodes)
     }
 
-    #[inline(always)]
-    fn get_check(&self, node_idx: u32) -> u32 {
-        self.node_ref(node_idx).get_check()
+    /// Returns the double array and the mapping table as plain words,
+    /// which can be searched without knowing the Rust layout, e.g., via FFI.
+    ///
+    /// # Returns
+    ///
+    /// A tuple of the node words and the code table in the following layout.
+    ///
+    /// - The node words interleave `base` and `check` of each node,
+    ///   i.e., `[base_0, check_0, base_1, check_1, ...]`, where node `0` is the root.
+    ///   The lower 31 bits of `base` are the base offset of children, or the value for a leaf.
+    ///   The MSB of `base` is set for a leaf.
+    ///   The lower 31 bits of `check` are the index of the parent.
+    ///   The MSB of `check` is set if the node has a leaf child labeled with code `0`.
+    ///   A vacant node has both words equal to `0x7fff_ffff`.
+    /// - The code table maps each character `c` to the code at index `c as usize`,
+    ///   where `u32::MAX` or an index out of the table means no code.
+    ///
+    /// The child of node `s` with code `x` is node `t = base_s ^ x` if `check_t == s`
+    /// (in the lower 31 bits). After following all the characters of a key from the root,
+    /// its value is stored in the reached node if it is a leaf,
+    /// or in the leaf child `base_s ^ 0` if the MSB of `check_s` is set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// let (nodes, table) = trie.to_flat_u32();
+    /// let other = Trie::from_flat_u32(&nodes, &table).unwrap();
+    /// assert_eq!(other.exact_match("世界中".chars()), Some(1));
+    /// ```
+    pub fn to_flat_u32(&self) -> (Vec<u32>, Vec<u32>) {
+        let nodes = self
+            .nodes
+            .iter()
+            .flat_map(|node| [node.base, node.check])
+            .collect();
+        (nodes, self.mapper.table().to_vec())
     }
 
-    #[inline(always)]
-    fn is_leaf(&self, node_idx: u32) -> bool {
-        self.node_ref(node_idx).is_leaf()
+    /// Creates a [`Trie`] from the words returned by [`Trie::to_flat_u32()`]
+    /// while checking that they are not broken.
+    ///
+    /// Keys kept by [`Builder::keep_keys()`](crate::Builder::keep_keys) and
+    /// payloads attached by [`Builder::build_from_payloads()`](crate::Builder::build_from_payloads)
+    /// are not restored.
+    ///
+    /// # Arguments
+    ///
+    /// - `nodes`: Interleaved words of the double array.
+    /// - `table`: Code table of characters.
+    ///
+    /// # Errors
+    ///
+    /// [`CrawdadError::Corrupted`](crate::errors::CrawdadError::Corrupted) will be returned when
+    ///
+    /// - `nodes` has an odd number of words, or
+    /// - the resulting trie is broken (see [`Trie::validate()`]).
+    pub fn from_flat_u32(nodes: &[u32], table: &[u32]) -> Result<Self> {
+        if nodes.len() % 2 != 0 {
+            return Err(crate::errors::CrawdadError::corrupted(
+                "number of node words must be even.",
+            ));
+        }
+        let trie = Self {
+            mapper: CodeMapper::from_table(table.to_vec()),
+            nodes: nodes
+                .chunks_exact(2)
+                .map(|words| Node {
+                    base: words[0],
+                    check: words[1],
+                })
+                .collect(),
+            keys: None,
+            payloads: None,
+            options: BuildOptions::default(),
+        };
+        trie.validate()?;
+        Ok(trie)
     }
 
-    #[inline(always)]
-    fn has_leaf(&self, node_idx: u32) -> bool {
-        self.node_ref(node_idx).has_leaf()
+    /// Writes all the records into a writer in the TSV format.
+    ///
+    /// Each record is written as a line of `key\tvalue\n` in the lexicographical order of keys,
+    /// which can be parsed to rebuild the same trie with [`Trie::from_records()`].
+    ///
+    /// # Arguments
+    ///
+    /// - `w`: Writer to which the records are written.
+    ///
+    /// # Errors
+    ///
+    /// [`std::io::Error`] will be returned when
+    ///
+    /// - a key contains a tab or line break, which cannot be represented in the format
+    ///   (the error kind is [`std::io::ErrorKind::InvalidData`]), or
+    /// - the writer fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let records = vec![("世界", 2), ("世界中", 3), ("国民", 2)];
+    /// let trie = Trie::from_records(records).unwrap();
+    ///
+    /// let mut buf = vec![];
+    /// trie.write_tsv(&mut buf).unwrap();
+    /// assert_eq!(buf, "世界\t2\n世界中\t3\n国民\t2\n".as_bytes());
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn write_tsv<W>(&self, w: &mut W) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        for (key, value) in self.iter() {
+            if key.contains(&['\t', '\n', '\r'][..]) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "keys must not contain tabs or line breaks.",
+                ));
+            }
+            writeln!(w, "{}\t{}", key, value)?;
+        }
+        Ok(())
     }
 
-    #[inline(always)]
-    fn get_leaf_idx(&self, node_idx: u32) -> u32 {
-        let leaf_idx = self.get_base(node_idx) ^ END_CODE;
-        debug_assert_eq!(self.get_check(leaf_idx), node_idx);
-        leaf_idx
+    /// Writes the serialized data structure into a writer.
+    ///
+    /// The written bytes are the same as [`Trie::serialize_to_vec()`],
+    /// but the node array is written in chunks instead of being copied into a whole buffer.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error will be returned when the writer fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// let mut buf = vec![];
+    /// trie.write_serialized(&mut buf).unwrap();
+    /// assert_eq!(buf, trie.serialize_to_vec());
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn write_serialized<W>(&self, w: &mut W) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        const CHUNK_NODES: usize = 4096;
+        let mut buf = Vec::with_capacity(CHUNK_NODES * Node::io_bytes());
+        format::serialize_header(&mut buf);
+        self.mapper.serialize_into_vec(&mut buf);
+        buf.extend_from_slice(&u32::try_from(self.nodes.len()).unwrap().to_le_bytes());
+        w.write_all(&buf)?;
+        for chunk in self.nodes.chunks(CHUNK_NODES) {
+            buf.clear();
+            for node in chunk {
+                buf.extend_from_slice(&node.serialize());
+            }
+            w.write_all(&buf)?;
+        }
+        buf.clear();
+        PayloadStore::serialize_into_vec(self.payloads.as_ref(), &mut buf);
+        w.write_all(&buf)
     }
 
+    /// Returns a value associated with an input key if exists.
+    ///
+    /// # Arguments
+    ///
+    /// - `key`: Search key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.exact_match("世界中".chars()), Some(1));
+    /// assert_eq!(trie.exact_match("日本中".chars()), None);
+    /// ```
     #[inline(always)]
-    fn get_value(&self, node_idx: u32) -> u32 {
-        debug_assert!(self.is_leaf(node_idx));
-        self.node_ref(node_idx).get_base()
-    }
-
-    /// Returns the total amount of heap used by this automaton in bytes.
-    pub fn heap_bytes(&self) -> usize {
-        self.mapper.heap_bytes() + self.nodes.len() * mem::size_of::<Node>()
-    }
-
-    /// Returns the total amount of bytes to serialize the data structure.
-    pub fn io_bytes(&self) -> usize {
-        self.mapper.io_bytes() + self.nodes.len() * Node::io_bytes() + mem::size_of::<u32>()
+    pub fn exact_match<I>(&self, key: I) -> Option<u32>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        self.find_leaf_idx(key)
+            .map(|leaf_idx| self.get_value(leaf_idx))
     }
 
-    /// Returns the number of reserved elements.
-    pub fn num_elems(&self) -> usize {
-        self.nodes.len()
+    /// Returns a value associated with an input key if exists,
+    /// together with the number of characters consumed in the search.
+    ///
+    /// The number is the length of the key in characters, obtained in the same walk.
+    ///
+    /// # Arguments
+    ///
+    /// - `key`: Search key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.exact_match_with_len("世界中".chars()), Some((1, 3)));
+    /// assert_eq!(trie.exact_match_with_len("日本中".chars()), None);
+    /// ```
+    pub fn exact_match_with_len<I>(&self, key: I) -> Option<(u32, usize)>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let mut len = 0;
+        self.exact_match(key.into_iter().inspect(|_| len += 1))
+            .map(|v| (v, len))
+    }
+
+    /// Returns the number of leading characters of a query that can be followed from the root,
+    /// i.e., the length of the longest prefix of the query shared with some key.
+    ///
+    /// The prefix does not need to be a key itself.
+    ///
+    /// # Arguments
+    ///
+    /// - `query`: Query string.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.prefix_depth("世界中で".chars()), 3);
+    /// assert_eq!(trie.prefix_depth("国際".chars()), 1);
+    /// assert_eq!(trie.prefix_depth("日本".chars()), 0);
+    /// ```
+    pub fn prefix_depth<I>(&self, query: I) -> usize
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let mut node_idx = 0;
+        let mut depth = 0;
+        for c in query {
+            match self
+                .mapper
+                .get(c)
+                .and_then(|mc| self.get_child_idx(node_idx, mc))
+            {
+                Some(child_idx) => node_idx = child_idx,
+                None => break,
+            }
+            depth += 1;
+        }
+        depth
+    }
+
+    /// Checks if each of input keys is stored, returning the flags in the input order.
+    ///
+    /// The keys are searched one after another in a tight loop without any allocation
+    /// other than the result, so that the node array stays in the cache across searches.
+    ///
+    /// # Arguments
+    ///
+    /// - `keys`: Search keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(
+    ///     trie.contains_batch(&["国民", "日本", "世界中", "世"]),
+    ///     vec![true, false, true, false]
+    /// );
+    /// ```
+    pub fn contains_batch<K>(&self, keys: &[K]) -> Vec<bool>
+    where
+        K: AsRef<str>,
+    {
+        keys.iter()
+            .map(|key| self.find_leaf_idx(key.as_ref().chars()).is_some())
+            .collect()
+    }
+
+    /// Returns a value associated with an input key if exists,
+    /// skipping characters out of the alphabet.
+    ///
+    /// It works as [`Trie::exact_match()`] but ignores up to `max_skips` characters
+    /// not appearing in any key, e.g., noise in OCR text.
+    /// The search fails once the budget is exceeded or a character in the alphabet
+    /// has no transition from the current node.
+    /// Note that [`Builder::identity_mapping()`] puts all the ASCII characters
+    /// in the alphabet, so they are never skipped.
+    ///
+    /// # Arguments
+    ///
+    /// - `key`: Search key.
+    /// - `max_skips`: Maximum number of characters to skip.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.exact_match_lenient("世・界中".chars(), 1), Some(1));
+    /// assert_eq!(trie.exact_match_lenient("世・界・中".chars(), 1), None);
+    /// assert_eq!(trie.exact_match_lenient("世民界".chars(), 1), None);
+    /// ```
+    pub fn exact_match_lenient<I>(&self, key: I, max_skips: usize) -> Option<u32>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let mut node_idx = 0;
+        let mut skips = 0;
+        for c in key {
+            if let Some(mc) = self.mapper.get(c) {
+                node_idx = self.get_child_idx(node_idx, mc)?;
+            } else if skips < max_skips {
+                skips += 1;
+            } else {
+                return None;
+            }
+        }
+        if self.is_leaf(node_idx) {
+            Some(self.get_value(node_idx))
+        } else if self.has_leaf(node_idx) {
+            Some(self.get_value(self.get_leaf_idx(node_idx)))
+        } else {
+            None
+        }
+    }
+
+    /// Returns a value associated with an input key if exists,
+    /// together with statistics of the search for profiling.
+    ///
+    /// It works as [`Trie::exact_match()`] but counts the accessed nodes,
+    /// so use [`Trie::exact_match()`] unless you need the statistics.
+    ///
+    /// # Arguments
+    ///
+    /// - `key`: Search key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// let (value, stats) = trie.exact_match_profiled("世界中".chars());
+    /// assert_eq!(value, Some(1));
+    /// assert_eq!(stats.nodes_visited, 3);
+    /// assert_eq!(stats.chars_consumed, 3);
+    /// assert!(!stats.end_marker_followed);
+    ///
+    /// // The leaf of "世界" is reached by the end marker from the node of "世界".
+    /// let (value, stats) = trie.exact_match_profiled("世界".chars());
+    /// assert_eq!(value, Some(0));
+    /// assert_eq!(stats.nodes_visited, 2);
+    /// assert_eq!(stats.chars_consumed, 2);
+    /// assert!(stats.end_marker_followed);
+    /// ```
+    pub fn exact_match_profiled<I>(&self, key: I) -> (Option<u32>, QueryStats)
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let mut stats = QueryStats::default();
+        let mut node_idx = 0;
+        for c in key {
+            stats.chars_consumed += 1;
+            match self
+                .mapper
+                .get(c)
+                .and_then(|mc| self.get_child_idx(node_idx, mc))
+            {
+                Some(child_idx) => {
+                    stats.nodes_visited += 1;
+                    node_idx = child_idx;
+                }
+                None => return (None, stats),
+            }
+        }
+        let value = if self.is_leaf(node_idx) {
+            Some(self.get_value(node_idx))
+        } else if self.has_leaf(node_idx) {
+            stats.end_marker_followed = true;
+            Some(self.get_value(self.get_leaf_idx(node_idx)))
+        } else {
+            None
+        };
+        (value, stats)
+    }
+
+    /// Returns the index of the leaf node for an input key if exists.
+    ///
+    /// The index identifies the entry of the key and can be used to get its value
+    /// via [`Trie::leaf_value()`].
+    /// Note that indices are stable only for the same trie,
+    /// i.e., they may change if the trie is built again from the same keys.
+    ///
+    /// # Arguments
+    ///
+    /// - `key`: Search key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// let node_idx = trie.exact_match_node("世界中".chars()).unwrap();
+    /// assert_eq!(trie.leaf_value(node_idx), Some(1));
+    /// assert_eq!(trie.exact_match_node("日本中".chars()), None);
+    /// ```
+    pub fn exact_match_node<I>(&self, key: I) -> Option<u32>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        self.find_leaf_idx(key)
+    }
+
+    /// Associates an existing key with a new value in place, without restructuring.
+    ///
+    /// # Arguments
+    ///
+    /// - `key`: Key whose value is updated.
+    /// - `value`: New value.
+    ///
+    /// # Returns
+    ///
+    /// The previous value of the key, or [`None`] if the key does not exist,
+    /// in which case the trie is not modified.
+    ///
+    /// # Errors
+    ///
+    /// [`CrawdadError`](crate::errors::CrawdadError) will be returned when
+    /// `value` exceeds the upper bound of values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let mut trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.update_value("世界中".chars(), 42).unwrap(), Some(1));
+    /// assert_eq!(trie.exact_match("世界中".chars()), Some(42));
+    /// assert_eq!(trie.update_value("日本中".chars(), 42).unwrap(), None);
+    /// ```
+    pub fn update_value<I>(&mut self, key: I, value: u32) -> Result<Option<u32>>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        if MAX_VALUE < value {
+            return Err(crate::errors::CrawdadError::scale("input value", MAX_VALUE));
+        }
+        // The key is collected only to find its slot in the kept keys.
+        let keep_keys = self.keys.is_some();
+        let mut chars = String::new();
+        let key = key.into_iter().inspect(|&c| {
+            if keep_keys {
+                chars.push(c);
+            }
+        });
+        let leaf_idx = match self.find_leaf_idx(key) {
+            Some(leaf_idx) => leaf_idx,
+            None => return Ok(None),
+        };
+        let prev = self.get_value(leaf_idx);
+        // Keeps IsLeaf = True
+        self.nodes[usize::try_from(leaf_idx).unwrap()].base = value | !OFFSET_MASK;
+        if let Some(keys) = self.keys.as_mut() {
+            let slot = keys.find(&chars).unwrap();
+            keys.update_value(slot, value);
+        }
+        Ok(Some(prev))
+    }
+
+    /// Returns the payload attached to an input key if exists.
+    ///
+    /// Payloads are attached by [`Builder::build_from_payloads()`](crate::Builder::build_from_payloads),
+    /// which associates each key with the index of its payload as the value.
+    /// [`None`] is returned if the trie has no payload.
+    /// Payloads are serialized with the trie and carried over by [`Extend`].
+    /// Since values are the indices of payloads, a value updated by [`Trie::update_value()`]
+    /// or added by [`Extend`] refers to the payload of the new index.
+    ///
+    /// # Arguments
+    ///
+    /// - `key`: Search key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Builder;
+    ///
+    /// let records = vec![("世界", "せかい"), ("世界中", "せかいじゅう"), ("国民", "こくみん")];
+    /// let trie = Builder::new()
+    ///     .build_from_payloads(records)
+    ///     .unwrap()
+    ///     .release_trie()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(trie.payload("国民".chars()), Some("こくみん".as_bytes()));
+    /// assert_eq!(trie.payload("日本".chars()), None);
+    /// ```
+    pub fn payload<I>(&self, key: I) -> Option<&[u8]>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let payloads = self.payloads.as_ref()?;
+        self.exact_match(key)
+            .and_then(|value| payloads.get(usize::try_from(value).unwrap()))
+    }
+
+    /// Returns the value stored in a leaf node if the given index points to a leaf.
+    ///
+    /// # Arguments
+    ///
+    /// - `node_idx`: Index of the leaf node, e.g., returned by [`Trie::exact_match_node()`].
+    pub fn leaf_value(&self, node_idx: u32) -> Option<u32> {
+        self.nodes
+            .get(usize::try_from(node_idx).unwrap())
+            .filter(|node| node.is_leaf())
+            .map(|node| node.get_base())
+    }
+
+    /// Returns the code of a character if it appears in keys,
+    /// which is given to [`Trie::transition()`].
+    ///
+    /// # Arguments
+    ///
+    /// - `c`: Character to be mapped.
+    #[inline(always)]
+    pub fn map_char(&self, c: char) -> Option<u32> {
+        self.mapper.get(c)
+    }
+
+    /// Returns the state reached from a state by a code if exists,
+    /// i.e., the raw transition function of the trie as a deterministic automaton.
+    ///
+    /// A state is the index of a node. Starting from [`Trie::ROOT`] and following
+    /// the codes given by [`Trie::map_char()`] traces the prefixes of keys,
+    /// and [`Trie::value_at()`] tells whether a state ends a key.
+    /// States are stable for the same trie, including after serialization,
+    /// but may change if the trie is built again from the same keys.
+    /// An invalid state or code just results in [`None`].
+    ///
+    /// # Arguments
+    ///
+    /// - `state`: Current state.
+    /// - `code`: Code of the next character.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// let mut state = Trie::ROOT;
+    /// for c in "世界".chars() {
+    ///     let code = trie.map_char(c).unwrap();
+    ///     state = trie.transition(state, code).unwrap();
+    /// }
+    /// assert_eq!(trie.value_at(state), Some(0));
+    /// assert_eq!(trie.transition(state, trie.map_char('民').unwrap()), None);
+    /// ```
+    #[inline(always)]
+    pub fn transition(&self, state: u32, code: u32) -> Option<u32> {
+        let node = self.nodes.get(usize::try_from(state).ok()?)?;
+        if code == END_CODE || node.is_leaf() || node.is_vacant() {
+            return None;
+        }
+        let child_idx = node.get_base() ^ code;
+        self.nodes
+            .get(usize::try_from(child_idx).ok()?)
+            .filter(|child| !child.is_vacant() && child.get_check() == state)
+            .map(|_| child_idx)
+    }
+
+    /// Returns the value of the key ending at a state if exists.
+    ///
+    /// # Arguments
+    ///
+    /// - `state`: State reached by [`Trie::transition()`].
+    pub fn value_at(&self, state: u32) -> Option<u32> {
+        let node = self.nodes.get(usize::try_from(state).ok()?)?;
+        if node.is_vacant() {
+            None
+        } else if node.is_leaf() {
+            Some(node.get_base())
+        } else if node.has_leaf() {
+            self.leaf_value(node.get_base() ^ END_CODE)
+        } else {
+            None
+        }
+    }
+
+    /// Checks if two input keys are associated with the same entry.
+    ///
+    /// Unlike comparing values returned by [`Trie::exact_match()`],
+    /// this distinguishes different keys that happen to have the same value.
+    ///
+    /// # Arguments
+    ///
+    /// - `a`: Search key.
+    /// - `b`: Search key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let records = vec![("世界", 2), ("世界中", 3), ("国民", 2)];
+    /// let trie = Trie::from_records(records).unwrap();
+    ///
+    /// assert!(trie.same_entry("世界".chars(), "世界".chars()));
+    /// assert!(!trie.same_entry("世界".chars(), "国民".chars()));
+    /// assert!(!trie.same_entry("世界".chars(), "日本".chars()));
+    /// ```
+    pub fn same_entry<I, J>(&self, a: I, b: J) -> bool
+    where
+        I: IntoIterator<Item = char>,
+        J: IntoIterator<Item = char>,
+    {
+        match (self.find_leaf_idx(a), self.find_leaf_idx(b)) {
+            (Some(x), Some(y)) => x == y,
+            _ => false,
+        }
+    }
+
+    /// Returns an iterator for common prefix search.
+    ///
+    /// The iterator reports all occurrences of keys starting from an input haystack, where
+    /// an occurrence consists of its associated value and ending positoin in characters.
+    /// Occurrences are reported in strictly ascending order of their ending positions,
+    /// so the last one is the longest match.
+    ///
+    /// # Examples
+    ///
+    /// You can find all occurrences of keys in a haystack by performing common prefix searches
+    /// at all starting positions.
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// let haystack: Vec<char> = "国民が世界中にて".chars().collect();
+    /// let mut matches = vec![];
+    ///
+    /// for i in 0..haystack.len() {
+    ///     for (v, j) in trie.common_prefix_search(haystack[i..].iter().copied()) {
+    ///         matches.push((v, i..i + j));
+    ///     }
+    /// }
+    ///
+    /// assert_eq!(
+    ///     matches,
+    ///     vec![(2, 0..2), (0, 3..5), (1, 3..6)]
+    /// );
+    /// ```
+    pub const fn common_prefix_search<I>(&self, haystack: I) -> CommonPrefixSearchIter<'_, I> {
+        CommonPrefixSearchIter {
+            haystack,
+            haystack_pos: 0,
+            trie: self,
+            node_idx: 0,
+        }
+    }
+
+    /// Returns an iterator for common prefix search that also reports matched keys.
+    ///
+    /// It works as [`Trie::common_prefix_search()`] except that an occurrence consists of
+    /// the matched key, its associated value, and ending position in characters.
+    /// The key is accumulated from the haystack characters consumed in the descent,
+    /// so no second traversal is needed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// let matches: Vec<_> = trie
+    ///     .common_prefix_search_with_keys("世界中にて".chars())
+    ///     .collect();
+    /// assert_eq!(
+    ///     matches,
+    ///     vec![("世界".to_string(), 0, 2), ("世界中".to_string(), 1, 3)]
+    /// );
+    /// ```
+    pub const fn common_prefix_search_with_keys<I>(
+        &self,
+        haystack: I,
+    ) -> CommonPrefixSearchWithKeysIter<'_, I> {
+        CommonPrefixSearchWithKeysIter {
+            haystack,
+            haystack_pos: 0,
+            key: String::new(),
+            trie: self,
+            node_idx: 0,
+        }
+    }
+
+    #[inline(always)]
+    fn find_leaf_idx<I>(&self, key: I) -> Option<u32>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let mut node_idx = 0;
+        for c in key {
+            node_idx = self
+                .mapper
+                .get(c)
+                .and_then(|mc| self.get_child_idx(node_idx, mc))?;
+        }
+        if self.is_leaf(node_idx) {
+            Some(node_idx)
+        } else if self.has_leaf(node_idx) {
+            Some(self.get_leaf_idx(node_idx))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the shortest occurrence of keys starting from an input haystack if exists,
+    /// where an occurrence consists of its associated value and ending position in characters.
+    ///
+    /// The search stops as soon as the first key is found.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.shortest_prefix_match("世界中に".chars()), Some((0, 2)));
+    /// assert_eq!(trie.shortest_prefix_match("国".chars()), None);
+    /// ```
+    pub fn shortest_prefix_match<I>(&self, haystack: I) -> Option<(u32, usize)>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        self.common_prefix_search(haystack.into_iter()).next()
+    }
+
+    /// Returns the number of keys that are prefixes of an input haystack.
+    ///
+    /// It is equivalent to `common_prefix_search(haystack).count()`
+    /// but does not compute values of occurrences.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.common_prefix_count("世界中に".chars()), 2);
+    /// assert_eq!(trie.common_prefix_count("国".chars()), 0);
+    /// ```
+    pub fn common_prefix_count<I>(&self, haystack: I) -> usize
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let mut count = 0;
+        let mut node_idx = 0;
+        for c in haystack {
+            if let Some(child_idx) = self
+                .mapper
+                .get(c)
+                .and_then(|mc| self.get_child_idx(node_idx, mc))
+            {
+                node_idx = child_idx;
+            } else {
+                break;
+            }
+            if self.is_leaf(node_idx) || self.has_leaf(node_idx) {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Calls a closure on each occurrence of keys starting from an input haystack,
+    /// where an occurrence consists of its associated value and ending position in characters.
+    ///
+    /// Occurrences are visited in the same order as [`Trie::common_prefix_search()`].
+    /// If the closure returns [`ControlFlow::Break`], the search stops immediately
+    /// without descending further.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use core::ops::ControlFlow;
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// let mut matches = vec![];
+    /// trie.for_each_common_prefix("世界中に".chars(), |v, j| {
+    ///     matches.push((v, j));
+    ///     ControlFlow::Break(())
+    /// });
+    /// assert_eq!(matches, vec![(0, 2)]);
+    /// ```
+    pub fn for_each_common_prefix<I, F>(&self, haystack: I, mut f: F)
+    where
+        I: IntoIterator<Item = char>,
+        F: FnMut(u32, usize) -> ControlFlow<()>,
+    {
+        for (v, j) in self.common_prefix_search(haystack.into_iter()) {
+            if f(v, j) == ControlFlow::Break(()) {
+                return;
+            }
+        }
+    }
+
+    /// Returns an iterator that reports all occurrences of keys in an input haystack.
+    ///
+    /// An occurrence consists of its associated value and range in characters.
+    /// It is equivalent to performing [`Trie::common_prefix_search()`]
+    /// at all starting positions of the haystack,
+    /// so occurrences are reported in ascending order of their starting positions,
+    /// and of their ending positions for the same start.
+    /// Hence no range is reported twice, since the range determines the key.
+    /// A key occurring at several positions, or inside a longer key, is reported
+    /// once for each of its ranges.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// let haystack: Vec<char> = "国民が世界中にて".chars().collect();
+    /// let matches: Vec<_> = trie.scan(&haystack).collect();
+    ///
+    /// assert_eq!(
+    ///     matches,
+    ///     vec![(2, 0..2), (0, 3..5), (1, 3..6)]
+    /// );
+    /// ```
+    pub fn scan<'t, 'h>(&'t self, haystack: &'h [char]) -> ScanIter<'t, 'h> {
+        ScanIter {
+            trie: self,
+            haystack,
+            start: 0,
+            matches: self.common_prefix_search(haystack.iter().copied()),
+        }
+    }
+
+    /// Returns an iterator that reports all occurrences of keys in an input text
+    /// with their byte ranges.
+    ///
+    /// It works as [`Trie::scan()`] on the characters of `text`, except that ranges are
+    /// in bytes, so that `&text[range]` is the matched key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// let text = "国民が世界中にて";
+    /// let matches: Vec<_> = trie.scan_str(text).collect();
+    ///
+    /// assert_eq!(
+    ///     matches,
+    ///     vec![(2, 0..6), (0, 9..15), (1, 9..18)]
+    /// );
+    /// assert_eq!(&text[9..18], "世界中");
+    /// ```
+    pub fn scan_str<'t, 'h>(&'t self, text: &'h str) -> ScanStrIter<'t, 'h> {
+        ScanStrIter {
+            trie: self,
+            text,
+            start: 0,
+            end: 0,
+            end_chars: 0,
+            matches: self.common_prefix_search(text.chars()),
+        }
+    }
+
+    /// Returns an iterator that splits an input haystack into tokens
+    /// by the greedy longest matching.
+    ///
+    /// From the beginning of the haystack, the iterator reports the longest key starting
+    /// at the current position as [`Token::Match`] and advances past it.
+    /// If no key starts at the position, it reports the character as [`Token::Unknown`]
+    /// and advances by one character.
+    /// Positions are in characters.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    /// use crawdad::trie::Token;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// let haystack: Vec<char> = "国民が世界中".chars().collect();
+    /// let tokens: Vec<_> = trie.tokenize(&haystack).collect();
+    ///
+    /// assert_eq!(
+    ///     tokens,
+    ///     vec![
+    ///         Token::Match { start: 0, end: 2, value: 2 },
+    ///         Token::Unknown { start: 2, end: 3 },
+    ///         Token::Match { start: 3, end: 6, value: 1 },
+    ///     ]
+    /// );
+    /// ```
+    pub const fn tokenize<'t, 'h>(&'t self, haystack: &'h [char]) -> TokenizeIter<'t, 'h> {
+        self.tokenize_with(haystack, MatchPolicy::Longest)
+    }
+
+    /// Returns an iterator that reports non-overlapping occurrences of keys in an input haystack
+    /// by the leftmost-longest matching.
+    ///
+    /// From the beginning of the haystack, the iterator reports the longest key starting
+    /// at the current position and advances past it.
+    /// If no key starts at the position, it advances by one character without reporting.
+    /// Hence occurrences never overlap and are reported in ascending order of positions.
+    /// An occurrence consists of its associated value and range in characters,
+    /// which is the same as [`Token::Match`] of [`Trie::tokenize()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "界中", "中に", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// let haystack: Vec<char> = "国民が世界中にて".chars().collect();
+    /// let matches: Vec<_> = trie.leftmost_longest(&haystack).collect();
+    ///
+    /// assert_eq!(matches, vec![(4, 0..2), (1, 3..6)]);
+    /// ```
+    pub const fn leftmost_longest<'t, 'h>(
+        &'t self,
+        haystack: &'h [char],
+    ) -> LeftmostLongestIter<'t, 'h> {
+        LeftmostLongestIter {
+            tokens: self.tokenize(haystack),
+        }
+    }
+
+    /// Returns an iterator that splits an input haystack into tokens
+    /// by the greedy matching with a given policy.
+    ///
+    /// It works as [`Trie::tokenize()`] except that, if multiple keys start at the current
+    /// position, the one to be reported is chosen according to `policy`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    /// use crawdad::trie::{MatchPolicy, Token};
+    ///
+    /// let records = vec![("世界", 5), ("世界中", 3), ("中国", 1)];
+    /// let trie = Trie::from_records(records).unwrap();
+    ///
+    /// let haystack: Vec<char> = "世界中国".chars().collect();
+    /// let tokens: Vec<_> = trie.tokenize_with(&haystack, MatchPolicy::MaxValue).collect();
+    ///
+    /// assert_eq!(
+    ///     tokens,
+    ///     vec![
+    ///         Token::Match { start: 0, end: 2, value: 5 },
+    ///         Token::Match { start: 2, end: 4, value: 1 },
+    ///     ]
+    /// );
+    /// ```
+    pub const fn tokenize_with<'t, 'h>(
+        &'t self,
+        haystack: &'h [char],
+        policy: MatchPolicy,
+    ) -> TokenizeIter<'t, 'h> {
+        TokenizeIter {
+            trie: self,
+            haystack,
+            pos: 0,
+            policy,
+        }
+    }
+
+    /// Returns the key associated with an input value in O(log n) time,
+    /// if the keys are kept by [`Builder::keep_keys()`](crate::Builder::keep_keys),
+    /// where `n` is the number of keys.
+    ///
+    /// The value is binary-searched in a table sorted by values, instead of
+    /// a table indexed by values, because values can be sparse up to `0x7fff_ffff`.
+    /// The key is then sliced from the kept buffer without walking the trie.
+    /// If multiple keys have the value, the lexicographically smallest one is returned.
+    ///
+    /// # Arguments
+    ///
+    /// - `value`: Value associated with the key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Builder;
+    ///
+    /// let records = vec![("世界", 2), ("世界中", 3), ("国民", 2)];
+    /// let trie = Builder::new()
+    ///     .keep_keys()
+    ///     .build_from_records(records)
+    ///     .unwrap()
+    ///     .release_trie()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(trie.key(2), Some("世界"));
+    /// assert_eq!(trie.key(3), Some("世界中"));
+    /// assert_eq!(trie.key(4), None);
+    /// ```
+    pub fn key(&self, value: u32) -> Option<&str> {
+        self.keys.as_ref()?.key_of_value(value)
+    }
+
+    /// Creates a new [`Trie`] with the union of key sets of two tries.
+    ///
+    /// For a key contained in both tries, the value is given by
+    /// `resolve(value_in_self, value_in_other)`.
+    ///
+    /// # Arguments
+    ///
+    /// - `other`: Trie to be merged.
+    /// - `resolve`: Function to resolve values of a key contained in both tries.
+    ///
+    /// # Errors
+    ///
+    /// [`CrawdadError`](crate::errors::CrawdadError) will be returned when
+    /// the scale of the resulting trie exceeds the expected one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let a = Trie::from_records([("世界", 0), ("世界中", 1)]).unwrap();
+    /// let b = Trie::from_records([("世界中", 3), ("国民", 2)]).unwrap();
+    ///
+    /// let trie = a.union(&b, u32::max).unwrap();
+    /// let records: Vec<_> = trie.iter().collect();
+    /// assert_eq!(
+    ///     records,
+    ///     vec![
+    ///         ("世界".to_string(), 0),
+    ///         ("世界中".to_string(), 3),
+    ///         ("国民".to_string(), 2),
+    ///     ]
+    /// );
+    /// ```
+    pub fn union<F>(&self, other: &Self, resolve: F) -> Result<Self>
+    where
+        F: FnMut(u32, u32) -> u32,
+    {
+        Self::from_records(self.merge_records(other, true, resolve))
+    }
+
+    /// Creates a new [`Trie`] with the intersection of key sets of two tries.
+    ///
+    /// The value of each key is given by `resolve(value_in_self, value_in_other)`.
+    ///
+    /// # Arguments
+    ///
+    /// - `other`: Trie to be intersected.
+    /// - `resolve`: Function to resolve values of a key contained in both tries.
+    ///
+    /// # Errors
+    ///
+    /// [`CrawdadError`](crate::errors::CrawdadError) will be returned when
+    /// the tries have no common key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let a = Trie::from_records([("世界", 0), ("世界中", 1)]).unwrap();
+    /// let b = Trie::from_records([("世界中", 3), ("国民", 2)]).unwrap();
+    ///
+    /// let trie = a.intersection(&b, |a, _| a).unwrap();
+    /// let records: Vec<_> = trie.iter().collect();
+    /// assert_eq!(records, vec![("世界中".to_string(), 1)]);
+    /// ```
+    pub fn intersection<F>(&self, other: &Self, resolve: F) -> Result<Self>
+    where
+        F: FnMut(u32, u32) -> u32,
+    {
+        Self::from_records(self.merge_records(other, false, resolve))
+    }
+
+    /// Merges the sorted records of two tries.
+    /// Keys contained in only one of them are kept if `keep_distinct` is true.
+    fn merge_records<F>(
+        &self,
+        other: &Self,
+        keep_distinct: bool,
+        mut resolve: F,
+    ) -> Vec<(String, u32)>
+    where
+        F: FnMut(u32, u32) -> u32,
+    {
+        diff::merge(self, other)
+            .filter_map(|merged| match merged {
+                Merged::Both(key, value1, value2) => Some((key, resolve(value1, value2))),
+                Merged::Left(record) | Merged::Right(record) => keep_distinct.then(|| record),
+            })
+            .collect()
+    }
+
+    /// Returns the characters that can follow an input prefix in some keys,
+    /// in ascending order.
+    ///
+    /// The result is empty if no key has the prefix or if the prefix is a key
+    /// extended by no other key. The end of a key is not reported as a character.
+    ///
+    /// # Arguments
+    ///
+    /// - `prefix`: Prefix of keys.
+    ///
+    /// # Note
+    ///
+    /// It takes time linear in the largest code point of characters in keys
+    /// to decode the children.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "世論調査", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.next_chars("世".chars()), vec!['界', '論']);
+    /// assert_eq!(trie.next_chars("世界".chars()), vec!['中']);
+    /// assert!(trie.next_chars("国民".chars()).is_empty());
+    /// ```
+    pub fn next_chars<I>(&self, prefix: I) -> Vec<char>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let mut node_idx = 0;
+        for c in prefix {
+            node_idx = match self
+                .mapper
+                .get(c)
+                .and_then(|mc| self.get_child_idx(node_idx, mc))
+            {
+                Some(child_idx) => child_idx,
+                None => return vec![],
+            };
+        }
+        if self.is_leaf(node_idx) {
+            return vec![];
+        }
+        self.mapper
+            .decode_if(|code| code != END_CODE && self.get_child_idx(node_idx, code).is_some())
+    }
+
+    /// Returns the number of characters that can follow an input prefix in some keys,
+    /// i.e., the length of [`Trie::next_chars()`] without decoding the characters.
+    ///
+    /// # Arguments
+    ///
+    /// - `prefix`: Prefix of keys.
+    ///
+    /// # Note
+    ///
+    /// It takes time linear in the alphabet size.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "世論調査", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.branching_factor("世".chars()), 2);
+    /// assert_eq!(trie.branching_factor("世界".chars()), 1);
+    /// assert_eq!(trie.branching_factor("国民".chars()), 0);
+    /// ```
+    pub fn branching_factor<I>(&self, prefix: I) -> usize
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let mut node_idx = 0;
+        for c in prefix {
+            node_idx = match self
+                .mapper
+                .get(c)
+                .and_then(|mc| self.get_child_idx(node_idx, mc))
+            {
+                Some(child_idx) => child_idx,
+                None => return 0,
+            };
+        }
+        (0..self.mapper.alphabet_size())
+            .filter(|&code| code != END_CODE && self.get_child_idx(node_idx, code).is_some())
+            .count()
+    }
+
+    /// Returns the lexicographically smallest key having an input prefix and its associated value
+    /// if exist, without enumerating all the keys under the prefix.
+    ///
+    /// # Arguments
+    ///
+    /// - `prefix`: Prefix of keys.
+    ///
+    /// # Note
+    ///
+    /// It takes time linear in the largest code point of characters in keys
+    /// plus the alphabet size times the length of the resulting key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "世論調査", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(
+    ///     trie.min_key_with_prefix("世".chars()),
+    ///     Some(("世界".to_string(), 0))
+    /// );
+    /// assert_eq!(trie.min_key_with_prefix("日本".chars()), None);
+    /// ```
+    pub fn min_key_with_prefix<I>(&self, prefix: I) -> Option<(String, u32)>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        self.extreme_key_with_prefix(prefix, false)
+    }
+
+    /// Returns the lexicographically largest key having an input prefix and its associated value
+    /// if exist, without enumerating all the keys under the prefix.
+    ///
+    /// # Arguments
+    ///
+    /// - `prefix`: Prefix of keys.
+    ///
+    /// # Note
+    ///
+    /// It takes time linear in the largest code point of characters in keys
+    /// plus the alphabet size times the length of the resulting key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "世論調査", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(
+    ///     trie.max_key_with_prefix("世".chars()),
+    ///     Some(("世論調査".to_string(), 2))
+    /// );
+    /// assert_eq!(trie.max_key_with_prefix("日本".chars()), None);
+    /// ```
+    pub fn max_key_with_prefix<I>(&self, prefix: I) -> Option<(String, u32)>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        self.extreme_key_with_prefix(prefix, true)
+    }
+
+    /// Returns the smallest and largest values associated with keys having an input prefix
+    /// if exist.
+    ///
+    /// If values are assigned in the lexicographical order of keys, e.g., by
+    /// [`Trie::from_keys()`] with keys sorted by [`prepare_keys()`](crate::prepare_keys),
+    /// the keys having the prefix are associated with all the values in the inclusive range.
+    ///
+    /// # Arguments
+    ///
+    /// - `prefix`: Prefix of keys.
+    ///
+    /// # Note
+    ///
+    /// It visits all the nodes under the prefix and takes time linear in their number
+    /// times the alphabet size.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "世論調査", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.value_range("世".chars()), Some((0, 2)));
+    /// assert_eq!(trie.value_range("国民".chars()), Some((3, 3)));
+    /// assert_eq!(trie.value_range("日本".chars()), None);
+    /// ```
+    pub fn value_range<I>(&self, prefix: I) -> Option<(u32, u32)>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let mut node_idx = 0;
+        for c in prefix {
+            node_idx = self
+                .mapper
+                .get(c)
+                .and_then(|mc| self.get_child_idx(node_idx, mc))?;
+        }
+        let mut range: Option<(u32, u32)> = None;
+        let mut stack = vec![node_idx];
+        while let Some(node_idx) = stack.pop() {
+            if self.is_leaf(node_idx) {
+                let value = self.get_value(node_idx);
+                range = Some(range.map_or((value, value), |(min, max)| {
+                    (min.min(value), max.max(value))
+                }));
+                continue;
+            }
+            stack.extend(
+                (0..self.mapper.alphabet_size())
+                    .filter_map(|code| self.get_child_idx(node_idx, code)),
+            );
+        }
+        range
+    }
+
+    /// Returns up to `limit` keys having an input prefix and their associated values,
+    /// which are the smallest ones in the lexicographical order,
+    /// without enumerating all the keys under the prefix.
+    ///
+    /// # Arguments
+    ///
+    /// - `prefix`: Prefix of keys.
+    /// - `limit`: Maximum number of results.
+    ///
+    /// # Note
+    ///
+    /// It takes time linear in the largest code point of characters in keys
+    /// plus the alphabet size times the number of visited nodes,
+    /// which stops growing once `limit` keys are found.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "世論調査", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(
+    ///     trie.predictive_search_limited("世".chars(), 2),
+    ///     vec![("世界".to_string(), 0), ("世界中".to_string(), 1)]
+    /// );
+    /// assert!(trie.predictive_search_limited("日本".chars(), 2).is_empty());
+    /// ```
+    pub fn predictive_search_limited<I>(&self, prefix: I, limit: usize) -> Vec<(String, u32)>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let mut matches = vec![];
+        let mut key = String::new();
+        let mut node_idx = 0;
+        for c in prefix {
+            match self
+                .mapper
+                .get(c)
+                .and_then(|mc| self.get_child_idx(node_idx, mc))
+            {
+                Some(child_idx) => node_idx = child_idx,
+                None => return matches,
+            }
+            key.push(c);
+        }
+        if limit == 0 {
+            return matches;
+        }
+        let decoder = self.mapper.decoder();
+        // Each entry has a node and the byte length of the key to its parent.
+        let mut stack = vec![(node_idx, key.len(), None)];
+        while let Some((node_idx, len, c)) = stack.pop() {
+            key.truncate(len);
+            if let Some(c) = c {
+                key.push(c);
+            }
+            if self.is_leaf(node_idx) {
+                matches.push((key.clone(), self.get_value(node_idx)));
+                if matches.len() == limit {
+                    break;
+                }
+                continue;
+            }
+            stack.extend(
+                self.children_desc(node_idx, &decoder)
+                    .into_iter()
+                    .map(|(c, child_idx)| (child_idx, key.len(), c)),
+            );
+        }
+        matches
+    }
+
+    /// Returns an iterator over keys and their associated values
+    /// in the lexicographical order of keys, borrowing the keys kept by
+    /// [`Builder::keep_keys()`](crate::Builder::keep_keys).
+    ///
+    /// Unlike [`Trie::iter()`], it allocates no string for each key.
+    /// [`None`] is returned if the keys are not kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Builder;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Builder::new()
+    ///     .keep_keys()
+    ///     .build_from_keys(&keys)
+    ///     .unwrap()
+    ///     .release_trie()
+    ///     .unwrap();
+    ///
+    /// let records: Vec<_> = trie.iter_borrowed().unwrap().collect();
+    /// assert_eq!(records, vec![("世界", 0), ("世界中", 1), ("国民", 2)]);
+    /// ```
+    pub fn iter_borrowed(&self) -> Option<BorrowedIter<'_>> {
+        self.keys
+            .as_ref()
+            .map(|keys| BorrowedIter { keys, slot: 0 })
+    }
+
+    /// Returns an iterator over keys and their associated values
+    /// in the lexicographical order of keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// let records: Vec<_> = trie.iter().collect();
+    /// assert_eq!(
+    ///     records,
+    ///     vec![
+    ///         ("世界".to_string(), 0),
+    ///         ("世界中".to_string(), 1),
+    ///         ("国民".to_string(), 2),
+    ///     ]
+    /// );
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// It takes `O(num_elems log num_elems)` time and `O(num_elems)` extra space
+    /// to create the iterator.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            trie: self,
+            leaves: LeafIter::new(&self.mapper, &self.nodes),
+        }
+    }
+
+    /// Returns an iterator over keys and their associated values
+    /// in the reverse lexicographical order of keys.
+    ///
+    /// It yields the same records as [`Trie::iter()`] in the opposite order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// let records: Vec<_> = trie.iter_rev().collect();
+    /// assert_eq!(
+    ///     records,
+    ///     vec![
+    ///         ("国民".to_string(), 2),
+    ///         ("世界中".to_string(), 1),
+    ///         ("世界".to_string(), 0),
+    ///     ]
+    /// );
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// It takes `O(num_elems log num_elems)` time and `O(num_elems)` extra space
+    /// to create the iterator.
+    pub fn iter_rev(&self) -> Iter<'_> {
+        Iter {
+            trie: self,
+            leaves: LeafIter::new_rev(&self.mapper, &self.nodes),
+        }
+    }
+
+    /// Returns an iterator over values in the lexicographical order of their keys.
+    ///
+    /// It yields the values of [`Trie::iter()`] without building key strings.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["国民", "世界", "世界中"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.values().collect::<Vec<_>>(), vec![1, 2, 0]);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// It takes `O(num_elems log num_elems)` time and `O(num_elems)` extra space
+    /// to create the iterator.
+    pub fn values(&self) -> Values<'_> {
+        Values {
+            trie: self,
+            leaves: LeafIter::new(&self.mapper, &self.nodes),
+        }
+    }
+
+    /// Returns an iterator over keys in the lexicographical order.
+    ///
+    /// It yields the keys of [`Trie::iter()`] without their values.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["国民", "世界", "世界中"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(
+    ///     trie.keys_only().collect::<Vec<_>>(),
+    ///     vec!["世界", "世界中", "国民"]
+    /// );
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// It takes `O(num_elems log num_elems)` time and `O(num_elems)` extra space
+    /// to create the iterator.
+    pub fn keys_only(&self) -> Keys<'_> {
+        Keys {
+            leaves: LeafIter::new(&self.mapper, &self.nodes),
+        }
+    }
+
+    /// Returns up to `limit` keys starting with a string within an edit distance of an input
+    /// prefix, together with their associated values and distances,
+    /// in the lexicographical order of keys.
+    ///
+    /// The distance of a key is the smallest Levenshtein distance between `prefix` and
+    /// a prefix of the key, where insertions, deletions, and substitutions of characters
+    /// cost one. Keys with distances no greater than `max_dist` are reported.
+    ///
+    /// # Arguments
+    ///
+    /// - `prefix`: Typed prefix of keys.
+    /// - `max_dist`: Maximum edit distance.
+    /// - `limit`: Maximum number of results.
+    ///
+    /// # Note
+    ///
+    /// It visits the nodes whose paths are within `max_dist` of some prefix of `prefix`,
+    /// and the nodes under the matched prefixes until `limit` keys are found.
+    /// Each visited node takes time linear in the alphabet size plus the length of `prefix`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "世論調査", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(
+    ///     trie.fuzzy_predictive_search("世階".chars(), 1, 10),
+    ///     vec![
+    ///         ("世界".to_string(), 0, 1),
+    ///         ("世界中".to_string(), 1, 1),
+    ///         ("世論調査".to_string(), 2, 1),
+    ///     ]
+    /// );
+    /// assert_eq!(
+    ///     trie.fuzzy_predictive_search("世界".chars(), 0, 10),
+    ///     vec![("世界".to_string(), 0, 0), ("世界中".to_string(), 1, 0)]
+    /// );
+    /// ```
+    pub fn fuzzy_predictive_search<I>(
+        &self,
+        prefix: I,
+        max_dist: usize,
+        limit: usize,
+    ) -> Vec<(String, u32, usize)>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let prefix: Vec<_> = prefix.into_iter().collect();
+        let mut matches = vec![];
+        if limit == 0 {
+            return matches;
+        }
+        let decoder = self.mapper.decoder();
+        let mut key = String::new();
+
+        // Each entry has a node, the byte length of the key to its parent, the label,
+        // the DP row of distances to the prefixes of `prefix` if some is within `max_dist`,
+        // and the smallest distance to `prefix` among the ancestors if within `max_dist`.
+        let row: Vec<_> = (0..=prefix.len()).collect();
+        let best = (row[prefix.len()] <= max_dist).then(|| row[prefix.len()]);
+        let mut stack = vec![(0, 0, None, Some(row), best)];
+        while let Some((node_idx, len, c, row, best)) = stack.pop() {
+            key.truncate(len);
+            if let Some(c) = c {
+                key.push(c);
+            }
+            if self.is_leaf(node_idx) {
+                if let Some(dist) = best {
+                    matches.push((key.clone(), self.get_value(node_idx), dist));
+                    if matches.len() == limit {
+                        break;
+                    }
+                }
+                continue;
+            }
+            for (c, child_idx) in self.children_desc(node_idx, &decoder) {
+                let (row, best) = match (c, row.as_ref()) {
+                    (Some(c), Some(row)) => {
+                        let next = edit_distance_step(&prefix, row, c);
+                        let dist = next[prefix.len()];
+                        let best = match best {
+                            Some(best) if best <= dist => Some(best),
+                            _ => (dist <= max_dist).then(|| dist),
+                        };
+                        let alive = next.iter().any(|&d| d <= max_dist);
+                        (alive.then(|| next), best)
+                    }
+                    _ => (row.clone(), best),
+                };
+                if row.is_some() || best.is_some() {
+                    stack.push((child_idx, key.len(), c, row, best));
+                }
+            }
+        }
+        matches
+    }
+
+    /// Returns keys matching a glob pattern and their associated values
+    /// in the lexicographical order of keys.
+    ///
+    /// In `pattern`, `?` matches any single character, `*` matches any sequence of
+    /// characters including the empty one, and the other characters match themselves.
+    /// Keys containing `?` or `*` can be matched only by the wildcards.
+    ///
+    /// # Arguments
+    ///
+    /// - `pattern`: Glob pattern.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "世論調査", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    ///
+    /// assert_eq!(trie.glob_match("世*中".chars()), vec![("世界中".to_string(), 1)]);
+    /// assert_eq!(trie.glob_match("?民".chars()), vec![("国民".to_string(), 3)]);
+    /// assert_eq!(
+    ///     trie.glob_match("世*".chars()),
+    ///     vec![
+    ///         ("世界".to_string(), 0),
+    ///         ("世界中".to_string(), 1),
+    ///         ("世論調査".to_string(), 2),
+    ///     ]
+    /// );
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The traversal simulates the pattern on every node at once instead of backtracking,
+    /// so it visits each node at most once and prunes nodes no pattern position can reach.
+    /// It takes `O(num_elems log num_elems + m * v)` time, where `m` is the length of
+    /// `pattern` and `v` is the number of visited nodes.
+    pub fn glob_match<I>(&self, pattern: I) -> Vec<(String, u32)>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let pattern: Vec<_> = pattern.into_iter().collect();
+        let decoder = self.mapper.decoder();
+        let table = ChildTable::new(&self.nodes, &decoder);
+
+        let mut matches = vec![];
+        let mut key = vec![];
+        let mut initial = vec![false; pattern.len() + 1];
+        initial[0] = true;
+        // Each entry has a node, the depth of its parent, and the pattern positions reaching it.
+        let mut stack = vec![(0, 0, glob_closure(&pattern, initial))];
+        while let Some((node_idx, depth, states)) = stack.pop() {
+            key.truncate(depth);
+            if node_idx != 0 {
+                let code = self.get_base(self.get_check(node_idx)) ^ node_idx;
+                if code != END_CODE {
+                    key.push(decoder[usize::try_from(code).unwrap()]);
+                }
+            }
+            if self.is_leaf(node_idx) {
+                if states[pattern.len()] {
+                    matches.push((key.iter().collect(), self.get_value(node_idx)));
+                }
+                continue;
+            }
+            for &child_idx in table.children(node_idx).iter().rev() {
+                let code = self.get_base(node_idx) ^ child_idx;
+                let next = if code == END_CODE {
+                    states.clone()
+                } else {
+                    glob_step(&pattern, &states, decoder[usize::try_from(code).unwrap()])
+                };
+                if next.contains(&true) {
+                    stack.push((child_idx, key.len(), next));
+                }
+            }
+        }
+        matches
+    }
+
+    fn extreme_key_with_prefix<I>(&self, prefix: I, largest: bool) -> Option<(String, u32)>
+    where
+        I: IntoIterator<Item = char>,
+    {
+        let mut key = String::new();
+        let mut node_idx = 0;
+        for c in prefix {
+            node_idx = self
+                .mapper
+                .get(c)
+                .and_then(|mc| self.get_child_idx(node_idx, mc))?;
+            key.push(c);
+        }
+        let decoder = self.mapper.decoder();
+        while !self.is_leaf(node_idx) {
+            let (is_char, c, child_idx) = self.extreme_child(node_idx, &decoder, largest)?;
+            if is_char {
+                key.push(c);
+            }
+            node_idx = child_idx;
+        }
+        Some((key, self.get_value(node_idx)))
+    }
+
+    /// Returns the children with their labels in the descending order,
+    /// where the child with [`END_CODE`] is labeled [`None`] and comes last.
+    ///
+    /// Pushing them to a stack makes the children visited in the lexicographical order.
+    fn children_desc(&self, node_idx: u32, decoder: &[char]) -> Vec<(Option<char>, u32)> {
+        let mut children: Vec<_> = (0..self.mapper.alphabet_size())
+            .filter_map(|code| {
+                self.get_child_idx(node_idx, code).map(|child_idx| {
+                    let c = (code != END_CODE).then(|| decoder[usize::try_from(code).unwrap()]);
+                    (c, child_idx)
+                })
+            })
+            .collect();
+        children.sort_unstable_by(|a, b| b.cmp(a));
+        children
+    }
+
+    /// Returns the child with the smallest or largest label in the lexicographical order,
+    /// where the child with [`END_CODE`] is the smallest.
+    fn extreme_child(
+        &self,
+        node_idx: u32,
+        decoder: &[char],
+        largest: bool,
+    ) -> Option<(bool, char, u32)> {
+        let children = (0..self.mapper.alphabet_size()).filter_map(|code| {
+            self.get_child_idx(node_idx, code).map(|child_idx| {
+                (
+                    code != END_CODE,
+                    decoder[usize::try_from(code).unwrap()],
+                    child_idx,
+                )
+            })
+        });
+        if largest {
+            children.max()
+        } else {
+            children.min()
+        }
+    }
+
+    #[inline(always)]
+    fn get_child_idx(&self, node_idx: u32, mc: u32) -> Option<u32> {
+        if self.is_leaf(node_idx) {
+            return None;
+        }
+        Some(self.get_base(node_idx) ^ mc)
+            .filter(|&child_idx| self.get_check(child_idx) == node_idx)
+    }
+
+    #[inline(always)]
+    fn node_ref(&self, node_idx: u32) -> &Node {
+        &self.nodes[usize::try_from(node_idx).unwrap()]
+    }
+
+    #[inline(always)]
+    fn get_base(&self, node_idx: u32) -> u32 {
+        self.node_ref(node_idx).get_base()
+    }
+
+    #[inline(always)]
+    fn get_check(&self, node_idx: u32) -> u32 {
+        self.node_ref(node_idx).get_check()
+    }
+
+    #[inline(always)]
+    fn is_leaf(&self, node_idx: u32) -> bool {
+        self.node_ref(node_idx).is_leaf()
+    }
+
+    #[inline(always)]
+    fn has_leaf(&self, node_idx: u32) -> bool {
+        self.node_ref(node_idx).has_leaf()
+    }
+
+    #[inline(always)]
+    fn get_leaf_idx(&self, node_idx: u32) -> u32 {
+        let leaf_idx = self.get_base(node_idx) ^ END_CODE;
+        debug_assert_eq!(self.get_check(leaf_idx), node_idx);
+        leaf_idx
+    }
+
+    #[inline(always)]
+    fn get_value(&self, node_idx: u32) -> u32 {
+        debug_assert!(self.is_leaf(node_idx));
+        self.node_ref(node_idx).get_base()
+    }
+
+    /// Removes all the keys, keeping the allocated memory of the node array and the mapper.
+    ///
+    /// The emptied trie can be queried, e.g., [`Trie::exact_match()`] always returns [`None`],
+    /// and its node array can be reused by [`Builder::reuse_buffers()`].
+    /// Since no trie built from keys is empty, [`Trie::validate()`] reports an error for it,
+    /// and its serialized bytes cannot be deserialized.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let mut trie = Trie::from_keys(["世界", "世界中"]).unwrap();
+    /// trie.clear();
+    ///
+    /// assert_eq!(trie.exact_match("世界".chars()), None);
+    /// assert_eq!(trie.iter().count(), 0);
+    /// ```
+    pub fn clear(&mut self) {
+        self.mapper.clear();
+        self.nodes.clear();
+        // A root without any child.
+        self.nodes.push(Node {
+            base: 0,
+            check: OFFSET_MASK,
+        });
+        self.keys = None;
+        self.payloads = None;
+    }
+
+    /// Touches all the memory regions of the data structure to pre-fault their pages.
+    ///
+    /// This is useful after loading the data structure from a memory-mapped file,
+    /// trading startup time for lower latency of the first queries.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crawdad::Trie;
+    ///
+    /// let keys = vec!["世界", "世界中", "国民"];
+    /// let trie = Trie::from_keys(&keys).unwrap();
+    /// trie.prewarm();
+    /// ```
+    pub fn prewarm(&self) {
+        self.mapper.prewarm();
+        utils::touch_pages(&self.nodes);
+    }
+
+    /// Returns the total amount of heap used by this automaton in bytes.
+    pub fn heap_bytes(&self) -> usize {
+        self.mapper.heap_bytes()
+            + self.nodes.len() * mem::size_of::<Node>()
+            + self.keys.as_ref().map_or(0, KeyStore::heap_bytes)
+            + self.payloads.as_ref().map_or(0, PayloadStore::heap_bytes)
+    }
+
+    /// Returns the total amount of bytes to serialize the data structure.
+    ///
+    /// It is computed without serialization and equals the length of the vector
+    /// returned by [`Trie::serialize_to_vec()`], so it can be used to preallocate a buffer.
+    pub fn io_bytes(&self) -> usize {
+        format::HEADER_BYTES
+            + self.mapper.io_bytes()
+            + self.nodes.len() * Node::io_bytes()
+            + mem::size_of::<u32>()
+            + PayloadStore::io_bytes(self.payloads.as_ref())
+    }
+
+    /// Returns the number of reserved elements.
+    pub fn num_elems(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns the number of vacant elements.
+    ///
+    /// # Note
+    ///
+    /// It takes `O(num_elems)` time.
+    pub fn num_vacants(&self) -> usize {
+        self.nodes.iter().filter(|nd| nd.is_vacant()).count()
+    }
+
+    /// Returns the number of leaf nodes.
+    ///
+    /// # Note
+    ///
+    /// It takes `O(num_elems)` time.
+    pub fn num_leaves(&self) -> usize {
+        self.nodes.iter().filter(|nd| nd.is_leaf()).count()
+    }
+
+    /// Returns the number of internal nodes having a leaf for a key ending at them.
+    ///
+    /// # Note
+    ///
+    /// It takes `O(num_elems)` time.
+    pub fn num_has_leaf(&self) -> usize {
+        self.nodes.iter().filter(|nd| nd.has_leaf()).count()
+    }
+}
+
+/// Iterator for common prefix search.
+pub struct CommonPrefixSearchIter<'t, I> {
+    haystack: I,
+    haystack_pos: usize,
+    trie: &'t Trie,
+    node_idx: u32,
+}
+
+impl<I> Iterator for CommonPrefixSearchIter<'_, I>
+where
+    I: Iterator<Item = char>,
+{
+    type Item = (u32, usize);
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Self::Item> {
+        for c in self.haystack.by_ref() {
+            let mc = self.trie.mapper.get(c)?;
+            self.node_idx = self.trie.get_child_idx(self.node_idx, mc)?;
+            self.haystack_pos += 1;
+            if self.trie.is_leaf(self.node_idx) {
+                return Some((self.trie.get_value(self.node_idx), self.haystack_pos));
+            } else if self.trie.has_leaf(self.node_idx) {
+                let leaf_idx = self.trie.get_leaf_idx(self.node_idx);
+                return Some((self.trie.get_value(leaf_idx), self.haystack_pos));
+            }
+        }
+        None
+    }
+}
+
+/// Iterator for common prefix search reporting matched keys,
+/// created by [`Trie::common_prefix_search_with_keys()`].
+pub struct CommonPrefixSearchWithKeysIter<'t, I> {
+    haystack: I,
+    haystack_pos: usize,
+    key: String,
+    trie: &'t Trie,
+    node_idx: u32,
+}
+
+impl<I> Iterator for CommonPrefixSearchWithKeysIter<'_, I>
+where
+    I: Iterator<Item = char>,
+{
+    type Item = (String, u32, usize);
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Self::Item> {
+        for c in self.haystack.by_ref() {
+            let mc = self.trie.mapper.get(c)?;
+            self.node_idx = self.trie.get_child_idx(self.node_idx, mc)?;
+            self.haystack_pos += 1;
+            self.key.push(c);
+            let leaf_idx = if self.trie.is_leaf(self.node_idx) {
+                self.node_idx
+            } else if self.trie.has_leaf(self.node_idx) {
+                self.trie.get_leaf_idx(self.node_idx)
+            } else {
+                continue;
+            };
+            let value = self.trie.get_value(leaf_idx);
+            return Some((self.key.clone(), value, self.haystack_pos));
+        }
+        None
+    }
+}
+
+impl<'t> IntoIterator for &'t Trie {
+    type Item = (String, u32);
+    type IntoIter = Iter<'t>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Adds records to the trie, replacing the values of existing keys.
+///
+/// The trie is rebuilt from its records merged with the new ones,
+/// where the last value given for a key wins.
+/// It is rebuilt with the options of the [`Builder`] that built the trie,
+/// e.g., [`Builder::keep_keys()`](crate::Builder::keep_keys) and
+/// [`Builder::identity_mapping()`](crate::Builder::identity_mapping),
+/// except [`Builder::value_offset()`](crate::Builder::value_offset) applying only to input keys.
+/// Since the options are not serialized, a deserialized trie is rebuilt with the default options.
+/// Payloads stay attached, where new values are taken as the indices of payloads.
+/// It takes `O((n + m) log (n + m))` time in addition to the construction,
+/// where `n` and `m` are the numbers of existing and new records,
+/// so adding records in a few large batches is much faster than in many small ones.
+/// Nothing is rebuilt for an empty batch.
+///
+/// # Panics
+///
+/// It panics when the construction fails (see [`Trie::from_records()`]),
+/// e.g., when a new key is empty.
+///
+/// # Examples
+///
+/// ```
+/// use crawdad::Trie;
+///
+/// let mut trie = Trie::from_records([("世界", 0), ("国民", 1)]).unwrap();
+/// trie.extend([("世界中", 2), ("国民", 3)]);
+///
+/// assert_eq!(trie.exact_match("世界".chars()), Some(0));
+/// assert_eq!(trie.exact_match("世界中".chars()), Some(2));
+/// assert_eq!(trie.exact_match("国民".chars()), Some(3));
+/// ```
+impl<K> Extend<(K, u32)> for Trie
+where
+    K: AsRef<str>,
+{
+    fn extend<T>(&mut self, records: T)
+    where
+        T: IntoIterator<Item = (K, u32)>,
+    {
+        let mut records = records.into_iter().peekable();
+        if records.peek().is_none() {
+            return;
+        }
+        let mut merged: BTreeMap<String, u32> = self.iter().collect();
+        merged.extend(records.map(|(key, value)| (key.as_ref().to_string(), value)));
+        let payloads = self.payloads.take();
+        *self = self
+            .options
+            .to_builder(false)
+            .build_from_records(merged)
+            .and_then(Builder::release_trie)
+            .expect("failed to rebuild the trie.");
+        self.payloads = payloads;
+    }
+}
+
+/// Token reported by [`TokenizeIter`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Token {
+    /// Occurrence of a key in `start..end`.
+    Match {
+        /// Starting position in characters.
+        start: usize,
+        /// Ending position in characters.
+        end: usize,
+        /// Value associated with the key.
+        value: u32,
+    },
+
+    /// Character in `start..end` starting no key, where `end == start + 1`.
+    Unknown {
+        /// Starting position in characters.
+        start: usize,
+        /// Ending position in characters.
+        end: usize,
+    },
+}
+
+/// Statistics of a search, returned by [`Trie::exact_match_profiled()`].
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueryStats {
+    /// Number of nodes transitioned to from the root by characters.
+    pub nodes_visited: usize,
+
+    /// Number of characters taken from the search key.
+    pub chars_consumed: usize,
+
+    /// Whether the leaf was reached by the end marker, in addition to `nodes_visited`.
+    pub end_marker_followed: bool,
+}
+
+/// Policy to choose one of the keys starting at the same position in tokenization.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MatchPolicy {
+    /// Chooses the longest key. This is the default.
+    Longest,
+
+    /// Chooses the shortest key.
+    Shortest,
+
+    /// Chooses the key with the smallest value. Ties are broken by choosing the longest.
+    MinValue,
+
+    /// Chooses the key with the largest value. Ties are broken by choosing the longest.
+    MaxValue,
+}
+
+impl Default for MatchPolicy {
+    fn default() -> Self {
+        Self::Longest
+    }
+}
+
+/// Iterator over all occurrences of keys in a haystack,
+/// created by [`Trie::scan()`].
+pub struct ScanIter<'t, 'h> {
+    trie: &'t Trie,
+    haystack: &'h [char],
+    start: usize,
+    matches: CommonPrefixSearchIter<'t, Copied<slice::Iter<'h, char>>>,
+}
+
+impl Iterator for ScanIter<'_, '_> {
+    type Item = (u32, Range<usize>);
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((value, len)) = self.matches.next() {
+                return Some((value, self.start..self.start + len));
+            }
+            if self.haystack.len() <= self.start + 1 {
+                return None;
+            }
+            self.start += 1;
+            self.matches = self
+                .trie
+                .common_prefix_search(self.haystack[self.start..].iter().copied());
+        }
+    }
+}
+
+/// Iterator over all occurrences of keys in a text with their byte ranges,
+/// created by [`Trie::scan_str()`].
+pub struct ScanStrIter<'t, 'h> {
+    trie: &'t Trie,
+    text: &'h str,
+    start: usize,
+    // Byte position and number of characters of the end of the last match from `start`.
+    end: usize,
+    end_chars: usize,
+    matches: CommonPrefixSearchIter<'t, Chars<'h>>,
+}
+
+impl Iterator for ScanStrIter<'_, '_> {
+    type Item = (u32, Range<usize>);
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((value, len)) = self.matches.next() {
+                self.end += self.text[self.end..]
+                    .chars()
+                    .take(len - self.end_chars)
+                    .map(char::len_utf8)
+                    .sum::<usize>();
+                self.end_chars = len;
+                return Some((value, self.start..self.end));
+            }
+            let c = self.text[self.start..].chars().next()?;
+            self.start += c.len_utf8();
+            self.end = self.start;
+            self.end_chars = 0;
+            self.matches = self
+                .trie
+                .common_prefix_search(self.text[self.start..].chars());
+        }
+    }
+}
+
+/// Iterator for greedy tokenization,
+/// created by [`Trie::tokenize()`] or [`Trie::tokenize_with()`].
+pub struct TokenizeIter<'t, 'h> {
+    trie: &'t Trie,
+    haystack: &'h [char],
+    pos: usize,
+    policy: MatchPolicy,
+}
+
+impl Iterator for TokenizeIter<'_, '_> {
+    type Item = Token;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.haystack.len() <= self.pos {
+            return None;
+        }
+        let start = self.pos;
+        let mut matches = self
+            .trie
+            .common_prefix_search(self.haystack[start..].iter().copied());
+        let m = match self.policy {
+            MatchPolicy::Longest => matches.last(),
+            MatchPolicy::Shortest => matches.next(),
+            MatchPolicy::MinValue => matches.min_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1))),
+            MatchPolicy::MaxValue => matches.max_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1))),
+        };
+        if let Some((value, len)) = m {
+            self.pos += len;
+            Some(Token::Match {
+                start,
+                end: self.pos,
+                value,
+            })
+        } else {
+            self.pos += 1;
+            Some(Token::Unknown {
+                start,
+                end: self.pos,
+            })
+        }
+    }
+}
+
+/// Iterator for the leftmost-longest matching,
+/// created by [`Trie::leftmost_longest()`].
+pub struct LeftmostLongestIter<'t, 'h> {
+    tokens: TokenizeIter<'t, 'h>,
+}
+
+impl Iterator for LeftmostLongestIter<'_, '_> {
+    type Item = (u32, Range<usize>);
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tokens.find_map(|token| match token {
+            Token::Match { start, end, value } => Some((value, start..end)),
+            Token::Unknown { .. } => None,
+        })
+    }
+}
+
+/// Iterator over borrowed keys and their associated values,
+/// created by [`Trie::iter_borrowed()`].
+pub struct BorrowedIter<'t> {
+    keys: &'t KeyStore,
+    slot: usize,
+}
+
+impl<'t> Iterator for BorrowedIter<'t> {
+    type Item = (&'t str, u32);
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.keys.len() <= self.slot {
+            return None;
+        }
+        let item = (self.keys.key(self.slot), self.keys.value(self.slot));
+        self.slot += 1;
+        Some(item)
+    }
+}
+
+/// Iterator over keys and their associated values,
+/// created by [`Trie::iter()`] or [`Trie::iter_rev()`].
+pub struct Iter<'t> {
+    trie: &'t Trie,
+    leaves: LeafIter<'t>,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = (String, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let leaf_idx = self.leaves.next_leaf()?;
+        let key = self.leaves.key().iter().collect();
+        Some((key, self.trie.get_value(leaf_idx)))
+    }
+}
+
+/// Iterator over values in the lexicographical order of their keys,
+/// created by [`Trie::values()`].
+pub struct Values<'t> {
+    trie: &'t Trie,
+    leaves: LeafIter<'t>,
+}
+
+impl Iterator for Values<'_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let leaf_idx = self.leaves.next_leaf()?;
+        Some(self.trie.get_value(leaf_idx))
+    }
+}
+
+/// Iterator over keys in the lexicographical order,
+/// created by [`Trie::keys_only()`].
+pub struct Keys<'t> {
+    leaves: LeafIter<'t>,
+}
+
+impl Iterator for Keys<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.leaves.next_leaf()?;
+        Some(self.leaves.key().iter().collect())
+    }
+}
+
+/// Returns the DP row of edit distances between the prefixes of `pattern` and
+/// the string of `row` followed by `c`.
+fn edit_distance_step(pattern: &[char], row: &[usize], c: char) -> Vec<usize> {
+    let mut next = Vec::with_capacity(row.len());
+    next.push(row[0] + 1);
+    for (i, &p) in pattern.iter().enumerate() {
+        let substitution = row[i] + usize::from(p != c);
+        next.push(substitution.min(row[i + 1] + 1).min(next[i] + 1));
+    }
+    next
+}
+
+/// Adds the positions reachable by letting `*` match the empty sequence.
+fn glob_closure(pattern: &[char], mut states: Vec<bool>) -> Vec<bool> {
+    for (i, &p) in pattern.iter().enumerate() {
+        if states[i] && p == '*' {
+            states[i + 1] = true;
+        }
+    }
+    states
+}
+
+/// Returns the positions reached from `states` by consuming `c`.
+fn glob_step(pattern: &[char], states: &[bool], c: char) -> Vec<bool> {
+    let mut next = vec![false; states.len()];
+    for (i, &p) in pattern.iter().enumerate() {
+        if !states[i] {
+            continue;
+        }
+        if p == '*' {
+            next[i] = true;
+        } else if p == '?' || p == c {
+            next[i + 1] = true;
+        }
+    }
+    glob_closure(pattern, next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_exact_match() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(
+                trie.exact_match(key.chars()),
+                Some(u32::try_from(i).unwrap())
+            );
+        }
+        assert_eq!(trie.exact_match("世".chars()), None);
+        assert_eq!(trie.exact_match("世論".chars()), None);
+        assert_eq!(trie.exact_match("世界中で".chars()), None);
+        assert_eq!(trie.exact_match("統計".chars()), None);
+        assert_eq!(trie.exact_match("統計調".chars()), None);
+        assert_eq!(trie.exact_match("日本".chars()), None);
+    }
+
+    #[test]
+    fn test_prefix_depth() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        assert_eq!(trie.prefix_depth("世論調整".chars()), 3);
+        assert_eq!(trie.prefix_depth("世論調査".chars()), 4);
+        assert_eq!(trie.prefix_depth("世論調査中".chars()), 4);
+        assert_eq!(trie.prefix_depth("世界中".chars()), 3);
+        assert_eq!(trie.prefix_depth("世界査".chars()), 2);
+        assert_eq!(trie.prefix_depth("統計調".chars()), 3);
+        assert_eq!(trie.prefix_depth("世国".chars()), 1);
+        assert_eq!(trie.prefix_depth("日本".chars()), 0);
+        assert_eq!(trie.prefix_depth("".chars()), 0);
+        assert_eq!(trie.prefix_depth("世界\u{ffff}".chars()), 2);
+    }
+
+    #[test]
+    fn test_branching_factor() {
+        let keys = vec!["世", "世界", "世界中", "世論調査", "世論", "国民", "国"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        for prefix in [
+            "",
+            "世",
+            "世界",
+            "世界中",
+            "世論",
+            "世論調",
+            "国",
+            "国民",
+            "日本",
+            "世界中で",
+        ] {
+            assert_eq!(
+                trie.branching_factor(prefix.chars()),
+                trie.next_chars(prefix.chars()).len()
+            );
+        }
+        assert_eq!(trie.branching_factor("".chars()), 2);
+        assert_eq!(trie.branching_factor("世".chars()), 2);
+        assert_eq!(trie.branching_factor("世論".chars()), 1);
+        assert_eq!(trie.branching_factor("世界中".chars()), 0);
+        assert_eq!(trie.branching_factor("日本".chars()), 0);
+    }
+
+    #[test]
+    fn test_transition() {
+        let keys = vec!["世", "世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        let run = |key: &str| {
+            key.chars().try_fold(Trie::ROOT, |state, c| {
+                trie.transition(state, trie.map_char(c)?)
+            })
+        };
+        for query in [
+            "世",
+            "世界",
+            "世界中",
+            "世論",
+            "世論調査",
+            "統計調査",
+            "",
+            "日本",
+            "世界中で",
+        ] {
+            assert_eq!(
+                run(query).and_then(|state| trie.value_at(state)),
+                trie.exact_match(query.chars()),
+            );
+        }
+        assert!(run("世論").is_some());
+        assert_eq!(trie.value_at(Trie::ROOT), None);
+
+        let state = run("世").unwrap();
+        assert_eq!(trie.transition(state, END_CODE), None);
+        assert_eq!(trie.transition(state, u32::MAX), None);
+        assert_eq!(trie.transition(u32::MAX, 1), None);
+        assert_eq!(trie.value_at(u32::MAX), None);
+        for state in 0..u32::try_from(trie.num_elems()).unwrap() {
+            for code in 0..trie.mapper.alphabet_size() + 1 {
+                trie.transition(state, code);
+            }
+            trie.value_at(state);
+        }
+    }
+
+    #[test]
+    fn test_contains_batch() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        let queries = vec![
+            "世界",
+            "世",
+            "世論",
+            "世界中で",
+            "統計調査",
+            "統計",
+            "日本",
+            "世論調査",
+            "世界中",
+        ];
+        let expected: Vec<_> = queries
+            .iter()
+            .map(|q| trie.exact_match(q.chars()).is_some())
+            .collect();
+        assert_eq!(trie.contains_batch(&queries), expected);
+        assert_eq!(
+            expected,
+            vec![true, false, false, false, true, false, false, true, true]
+        );
+        assert!(trie.contains_batch::<&str>(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_exact_match_lenient() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(
+                trie.exact_match_lenient(key.chars(), 0),
+                Some(u32::try_from(i).unwrap())
+            );
+        }
+        assert_eq!(trie.exact_match_lenient("世界?中".chars(), 1), Some(1));
+        assert_eq!(trie.exact_match_lenient("?世界中".chars(), 1), Some(1));
+        assert_eq!(trie.exact_match_lenient("世界中?".chars(), 1), Some(1));
+        assert_eq!(trie.exact_match_lenient("世界?中".chars(), 0), None);
+        assert_eq!(trie.exact_match_lenient("世?界?中".chars(), 1), None);
+        assert_eq!(trie.exact_match_lenient("世?界?中".chars(), 2), Some(1));
+        // Characters in other keys are not skipped.
+        assert_eq!(trie.exact_match_lenient("世界調中".chars(), 1), None);
+        assert_eq!(trie.exact_match_lenient("世界調中".chars(), 4), None);
+        assert_eq!(trie.exact_match_lenient("世論?".chars(), 1), None);
+    }
+
+    #[test]
+    fn test_exact_match_lenient_identity_mapping() {
+        let keys = vec!["ab", "abc"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        assert_eq!(trie.exact_match_lenient("a-b".chars(), 1), Some(0));
+        assert_eq!(trie.exact_match_lenient("a-bあc".chars(), 2), Some(1));
+        assert_eq!(trie.exact_match_lenient("a-bあc".chars(), 1), None);
+        assert_eq!(trie.exact_match_lenient("a".chars(), 1), None);
+
+        // ASCII characters are in the alphabet, so only the others are skipped.
+        let trie = Builder::new()
+            .identity_mapping()
+            .build_from_keys(&keys)
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        assert_eq!(trie.exact_match_lenient("a-b".chars(), 1), None);
+        assert_eq!(trie.exact_match_lenient("aあb".chars(), 1), Some(0));
+        assert_eq!(trie.exact_match_lenient("aあbいc".chars(), 2), Some(1));
+        assert_eq!(trie.exact_match_lenient("aあbいc".chars(), 1), None);
+    }
+
+    #[test]
+    fn test_query_end_marker() {
+        let trie = Trie::from_records([("世界", 1000), ("世界中", 1)]).unwrap();
+        assert_eq!(trie.exact_match("世界\u{ffff}".chars()), None);
+        assert_eq!(trie.exact_match("世界\u{ffff}中".chars()), None);
+        assert_eq!(trie.exact_match("\u{ffff}".chars()), None);
+        assert_eq!(trie.exact_match_node("世界\u{ffff}".chars()), None);
+        assert_eq!(
+            trie.exact_match_lenient("世界\u{ffff}".chars(), 1),
+            Some(1000)
+        );
+        assert_eq!(
+            trie.common_prefix_search("世界\u{ffff}中".chars())
+                .collect::<Vec<_>>(),
+            vec![(1000, 2)]
+        );
+        assert_eq!(trie.common_prefix_count("世界\u{ffff}".chars()), 1);
+        assert_eq!(trie.shortest_prefix_match("\u{ffff}".chars()), None);
+        assert_eq!(trie.next_chars("世界\u{ffff}".chars()), vec![]);
+        assert_eq!(trie.min_key_with_prefix("世界\u{ffff}".chars()), None);
+    }
+
+    #[test]
+    fn test_common_prefix_search() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+
+        let haystack: Vec<_> = "世界中の統計世論調査".chars().collect();
+        let mut matches = vec![];
+
+        for i in 0..haystack.len() {
+            for (v, j) in trie.common_prefix_search(haystack[i..].iter().copied()) {
+                matches.push((v, i..i + j));
+            }
+        }
+        assert_eq!(matches, vec![(0, 0..2), (1, 0..3), (2, 6..10)]);
+    }
+
+    #[test]
+    fn test_matches_are_end_sorted() {
+        let keys = vec!["世", "世界", "世界中", "世界中で", "世論", "世論調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+
+        for haystack in ["世界中で", "世界中の", "世論調査", "世論調", "世"] {
+            let ends: Vec<_> = trie
+                .common_prefix_search(haystack.chars())
+                .map(|(_, j)| j)
+                .collect();
+            assert!(!ends.is_empty());
+            assert!(ends.windows(2).all(|w| w[0] < w[1]));
+        }
+    }
+
+    #[test]
+    fn test_prewarm() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        trie.prewarm();
+    }
+
+    #[test]
+    fn test_iter() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        let expected: Vec<_> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, k)| (k.to_string(), u32::try_from(i).unwrap()))
+            .collect();
+        assert_eq!(trie.iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_iter_unsorted_records() {
+        let records = vec![
+            ("b", 3),
+            ("a\0", 1),
+            ("ab", 4),
+            ("a", 1),
+            ("abc", 5),
+            ("\0", 9),
+        ];
+        let trie = Trie::from_records(records.clone()).unwrap();
+        let mut expected: Vec<_> = records.iter().map(|&(k, v)| (k.to_string(), v)).collect();
+        expected.sort_unstable();
+        assert_eq!(trie.iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_iter_rev() {
+        let records = vec![
+            ("b", 3),
+            ("a\0", 1),
+            ("ab", 4),
+            ("a", 1),
+            ("abc", 5),
+            ("\0", 9),
+        ];
+        let trie = Trie::from_records(records.clone()).unwrap();
+        let mut expected: Vec<_> = trie.iter().collect();
+        expected.reverse();
+        assert_eq!(trie.iter_rev().collect::<Vec<_>>(), expected);
+
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        let mut expected: Vec<_> = trie.iter().collect();
+        expected.reverse();
+        assert_eq!(trie.iter_rev().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_values_and_keys_only() {
+        let keys = vec!["世", "世界", "世界中", "世論調査", "国民", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        assert_eq!(
+            trie.values().collect::<Vec<_>>(),
+            (0..u32::try_from(keys.len()).unwrap()).collect::<Vec<_>>()
+        );
+        assert_eq!(trie.keys_only().collect::<Vec<_>>(), keys);
+
+        let records = vec![("b", 3), ("a\0", 1), ("ab", 4), ("a", 1), ("abc", 5)];
+        let trie = Trie::from_records(records).unwrap();
+        let (expected_keys, expected_values): (Vec<_>, Vec<_>) = trie.iter().unzip();
+        assert_eq!(trie.values().collect::<Vec<_>>(), expected_values);
+        assert_eq!(trie.keys_only().collect::<Vec<_>>(), expected_keys);
+    }
+
+    #[test]
+    fn test_value_range() {
+        let keys =
+            crate::prepare_keys(["世", "世界", "世界中", "世論調査", "国民", "統計調査", "国"]);
+        let trie = Trie::from_keys(&keys).unwrap();
+        for prefix in [
+            "",
+            "世",
+            "世界",
+            "世界中",
+            "世論",
+            "国",
+            "国民",
+            "統計調",
+            "日本",
+            "世界中で",
+        ] {
+            let values = || {
+                keys.iter()
+                    .enumerate()
+                    .filter(|(_, key)| key.starts_with(prefix))
+                    .map(|(i, _)| u32::try_from(i).unwrap())
+            };
+            let expected = values().min().zip(values().max());
+            assert_eq!(trie.value_range(prefix.chars()), expected);
+            if let Some((min, max)) = expected {
+                assert_eq!(values().count(), usize::try_from(max - min + 1).unwrap());
+            }
+        }
+
+        let trie =
+            Trie::from_records([("世界", 5), ("世界中", 1), ("世論", 9), ("国民", 0)]).unwrap();
+        assert_eq!(trie.value_range("世".chars()), Some((1, 9)));
+        assert_eq!(trie.value_range("世界".chars()), Some((1, 5)));
+    }
+
+    #[test]
+    fn test_predictive_search_limited() {
+        let keys = vec!["世", "世界", "世界中", "世論調査", "国民", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        let all: Vec<_> = trie.iter().collect();
+        for prefix in [
+            "",
+            "世",
+            "世界",
+            "世論",
+            "国民",
+            "統計調",
+            "日本",
+            "世界中で",
+        ] {
+            let under: Vec<_> = all
+                .iter()
+                .filter(|(key, _)| key.starts_with(prefix))
+                .cloned()
+                .collect();
+            for limit in 0..=keys.len() + 1 {
+                let expected: Vec<_> = under.iter().take(limit).cloned().collect();
+                assert_eq!(
+                    trie.predictive_search_limited(prefix.chars(), limit),
+                    expected
+                );
+            }
+        }
+        assert_eq!(
+            trie.predictive_search_limited("世".chars(), 3),
+            vec![
+                ("世".to_string(), 0),
+                ("世界".to_string(), 1),
+                ("世界中".to_string(), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_predictive_search() {
+        let keys = vec![
+            "世",
+            "世界",
+            "世界中",
+            "世論調査",
+            "国民",
+            "統計調査",
+            "統計",
+        ];
+        let trie = Trie::from_keys(&keys).unwrap();
+        let all: Vec<_> = trie.iter().collect();
+
+        // Brute force over all the prefixes of all the keys.
+        let distance = |a: &[char], b: &[char]| {
+            let mut row: Vec<_> = (0..=a.len()).collect();
+            for &c in b {
+                row = edit_distance_step(a, &row, c);
+            }
+            row[a.len()]
+        };
+        for prefix in [
+            "",
+            "世",
+            "世階",
+            "正解",
+            "世界中",
+            "続計",
+            "国名",
+            "日本",
+            "世論調査書",
+        ] {
+            let prefix: Vec<char> = prefix.chars().collect();
+            for max_dist in 0..3 {
+                let expected: Vec<_> = all
+                    .iter()
+                    .filter_map(|(key, value)| {
+                        let key_chars: Vec<_> = key.chars().collect();
+                        let dist = (0..=key_chars.len())
+                            .map(|j| distance(&prefix, &key_chars[..j]))
+                            .min()
+                            .unwrap();
+                        (dist <= max_dist).then(|| (key.clone(), *value, dist))
+                    })
+                    .collect();
+                for limit in [0, 1, 3, keys.len()] {
+                    assert_eq!(
+                        trie.fuzzy_predictive_search(prefix.iter().copied(), max_dist, limit),
+                        expected.iter().take(limit).cloned().collect::<Vec<_>>()
+                    );
+                }
+            }
+        }
+
+        // A typo in the prefix still suggests the intended completions.
+        assert_eq!(
+            trie.fuzzy_predictive_search("続計".chars(), 1, 10),
+            vec![("統計".to_string(), 6, 1), ("統計調査".to_string(), 5, 1)]
+        );
+    }
+
+    #[test]
+    fn test_glob_match() {
+        let keys = vec![
+            "世",
+            "世界",
+            "世界中",
+            "世論調査",
+            "国民",
+            "市民",
+            "統計調査",
+        ];
+        let trie = Trie::from_keys(&keys).unwrap();
+        let glob = |pattern: &str| {
+            trie.glob_match(pattern.chars())
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            trie.glob_match("世*中".chars()),
+            vec![("世界中".to_string(), 2)]
+        );
+        assert_eq!(glob("??民"), Vec::<String>::new());
+        assert_eq!(glob("?民"), vec!["国民", "市民"]);
+        assert_eq!(glob("??"), vec!["世界", "国民", "市民"]);
+        assert_eq!(glob("世*"), vec!["世", "世界", "世界中", "世論調査"]);
+        assert_eq!(glob("*調査"), vec!["世論調査", "統計調査"]);
+        assert_eq!(glob("*界*"), vec!["世界", "世界中"]);
+        assert_eq!(glob("世?*"), vec!["世界", "世界中", "世論調査"]);
+        assert_eq!(glob("世界"), vec!["世界"]);
+        assert_eq!(glob("世界中で"), Vec::<String>::new());
+        assert_eq!(glob(""), Vec::<String>::new());
+
+        let mut all: Vec<_> = keys.iter().map(|k| k.to_string()).collect();
+        all.sort_unstable();
+        assert_eq!(glob("*"), all);
+        assert_eq!(glob("**"), all);
+        assert_eq!(glob("*?*"), all);
+    }
+
+    #[test]
+    fn test_glob_match_wildcard_keys() {
+        let trie = Trie::from_keys(["*", "?a", "a*", "ab"]).unwrap();
+        let glob = |pattern: &str| {
+            trie.glob_match(pattern.chars())
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(glob("?"), vec!["*"]);
+        assert_eq!(glob("a?"), vec!["a*", "ab"]);
+        assert_eq!(glob("*a"), vec!["?a"]);
+    }
+
+    #[test]
+    fn test_shortest_prefix_match() {
+        let keys = vec!["世", "世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        assert_eq!(trie.shortest_prefix_match("世界中で".chars()), Some((0, 1)));
+        assert_eq!(trie.shortest_prefix_match("世論調査".chars()), Some((0, 1)));
+        assert_eq!(
+            trie.shortest_prefix_match("統計調査で".chars()),
+            Some((4, 4))
+        );
+        assert_eq!(trie.shortest_prefix_match("統計".chars()), None);
+        assert_eq!(trie.shortest_prefix_match("日本".chars()), None);
+    }
+
+    #[test]
+    fn test_common_prefix_count() {
+        let keys = vec!["世", "世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        for haystack in [
+            "世界中で",
+            "世論調査",
+            "世論調",
+            "統計調査で",
+            "統計",
+            "日本",
+            "",
+        ] {
+            assert_eq!(
+                trie.common_prefix_count(haystack.chars()),
+                trie.common_prefix_search(haystack.chars()).count()
+            );
+        }
+        assert_eq!(trie.common_prefix_count("世界中で".chars()), 3);
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        let mut records = vec![];
+        for (key, value) in &trie {
+            records.push((key, value));
+        }
+        let expected: Vec<_> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, k)| (k.to_string(), u32::try_from(i).unwrap()))
+            .collect();
+        assert_eq!(records, expected);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_write_tsv() {
+        let records = vec![("世界", 3), ("世界中", 1), ("世論調査", 4), ("統計調査", 1)];
+        let trie = Trie::from_records(records).unwrap();
+
+        let mut buf = vec![];
+        trie.write_tsv(&mut buf).unwrap();
+
+        let text = core::str::from_utf8(&buf).unwrap();
+        let reloaded: Vec<_> = text
+            .lines()
+            .map(|line| {
+                let (key, value) = line.split_once('\t').unwrap();
+                (key, value.parse().unwrap())
+            })
+            .collect();
+        let other = Trie::from_records(reloaded).unwrap();
+        assert_eq!(trie.serialize_to_vec(), other.serialize_to_vec());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_write_tsv_invalid_key() {
+        let trie = Trie::from_keys(["世界", "世\t界"]).unwrap();
+        let mut buf = vec![];
+        let e = trie.write_tsv(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_num_leaves() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        assert_eq!(trie.num_leaves(), 4);
+        assert_eq!(trie.num_has_leaf(), 1);
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let keys = vec!["世界", "世界中", "国民"];
+        let trie = Trie::from_keys(&keys).unwrap();
+
+        let haystack: Vec<_> = "国民が世界中にて".chars().collect();
+        let tokens: Vec<_> = trie.tokenize(&haystack).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Match {
+                    start: 0,
+                    end: 2,
+                    value: 2
+                },
+                Token::Unknown { start: 2, end: 3 },
+                Token::Match {
+                    start: 3,
+                    end: 6,
+                    value: 1
+                },
+                Token::Unknown { start: 6, end: 7 },
+                Token::Unknown { start: 7, end: 8 },
+            ]
+        );
+        assert_eq!(trie.tokenize(&[]).next(), None);
+    }
+
+    #[test]
+    fn test_leftmost_longest() {
+        let records = vec![
+            ("世", 0),
+            ("世界", 1),
+            ("世界中", 2),
+            ("界中", 3),
+            ("中で", 4),
+            ("で", 5),
+            ("統計調査", 6),
+            ("調査票", 7),
+        ];
+        let trie = Trie::from_records(records).unwrap();
+
+        // 世界中|で|は|統計調査|票
+        let haystack: Vec<_> = "世界中では統計調査票".chars().collect();
+        let matches: Vec<_> = trie.leftmost_longest(&haystack).collect();
+        assert_eq!(matches, vec![(2, 0..3), (5, 3..4), (6, 5..9)]);
+
+        // 界中|で|世
+        let haystack: Vec<_> = "界中で世".chars().collect();
+        let matches: Vec<_> = trie.leftmost_longest(&haystack).collect();
+        assert_eq!(matches, vec![(3, 0..2), (5, 2..3), (0, 3..4)]);
+
+        assert_eq!(trie.leftmost_longest(&[]).next(), None);
+        assert_eq!(trie.leftmost_longest(&['日', '本']).next(), None);
+    }
+
+    #[test]
+    fn test_tokenize_with() {
+        let records = vec![
+            ("世", 4),
+            ("世界", 2),
+            ("世界中", 4),
+            ("界中", 1),
+            ("中", 2),
+        ];
+        let trie = Trie::from_records(records).unwrap();
+        let haystack: Vec<_> = "世界中で".chars().collect();
+
+        let tokenize = |policy| -> Vec<_> {
+            trie.tokenize_with(&haystack, policy)
+                .filter_map(|t| match t {
+                    Token::Match { start, end, value } => Some((start..end, value)),
+                    Token::Unknown { .. } => None,
+                })
+                .collect()
+        };
+        assert_eq!(tokenize(MatchPolicy::Longest), vec![(0..3, 4)]);
+        assert_eq!(tokenize(MatchPolicy::Shortest), vec![(0..1, 4), (1..3, 1)]);
+        assert_eq!(tokenize(MatchPolicy::MinValue), vec![(0..2, 2), (2..3, 2)]);
+        assert_eq!(tokenize(MatchPolicy::MaxValue), vec![(0..3, 4)]);
+        assert_eq!(
+            trie.tokenize_with(&haystack, MatchPolicy::default())
+                .collect::<Vec<_>>(),
+            trie.tokenize(&haystack).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_serialize() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+
+        let bytes = trie.serialize_to_vec();
+        assert_eq!(trie.io_bytes(), bytes.len());
+
+        let (other, remain) = Trie::deserialize_from_slice(&bytes);
+        assert!(remain.is_empty());
+
+        assert_eq!(trie.mapper, other.mapper);
+        assert_eq!(trie.nodes, other.nodes);
+    }
+
+    #[test]
+    fn test_serialize_little_endian() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        let bytes = trie.serialize_to_vec();
+
+        // Encodes every word byte by byte, independently of the host byte order.
+        let le_bytes =
+            |x: u32| [0, 8, 16, 24].map(|shift| u8::try_from((x >> shift) & 0xff).unwrap());
+        let (nodes, table) = trie.to_flat_u32();
+        let producer = env!("CARGO_PKG_VERSION");
+        let header = [
+            u32::from_le_bytes(*b"CRWD"),
+            format::FORMAT_VERSION,
+            u32::try_from(producer.len()).unwrap(),
+        ];
+        let mut words = vec![u32::try_from(table.len()).unwrap()];
+        words.extend_from_slice(&table);
+        words.push(trie.mapper.alphabet_size());
+        words.push(u32::try_from(nodes.len() / 2).unwrap());
+        words.extend_from_slice(&nodes);
+        // No payload
+        words.push(0);
+        let body: Vec<_> = words.into_iter().flat_map(le_bytes).collect();
+        let mut expected: Vec<_> = header.into_iter().flat_map(le_bytes).collect();
+        expected.extend_from_slice(producer.as_bytes());
+        expected.extend_from_slice(&body);
+        assert_eq!(bytes, expected);
+
+        // The same bytes are decoded on a big-endian host.
+        let swapped: Vec<_> = body
+            .chunks_exact(4)
+            .map(|b| u32::from_be_bytes([b[3], b[2], b[1], b[0]]))
+            .collect();
+        assert_eq!(
+            &swapped[swapped.len() - nodes.len() - 1..swapped.len() - 1],
+            &nodes[..]
+        );
+    }
+
+    #[test]
+    fn test_try_deserialize() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+
+        let mut bytes = trie.serialize_to_vec();
+        bytes.push(42);
+        let (other, remain) = Trie::try_deserialize_from_slice(&bytes).unwrap();
+        assert_eq!(remain, &[42]);
+        assert_eq!(trie.mapper, other.mapper);
+        assert_eq!(trie.nodes, other.nodes);
+
+        for len in 0..trie.io_bytes() {
+            assert!(Trie::try_deserialize_from_slice(&bytes[..len]).is_err());
+        }
+
+        // Flips bits of the bytes following the code mapper.
+        let beg = format::HEADER_BYTES + trie.mapper.io_bytes();
+        for i in beg..bytes.len() {
+            let mut broken = bytes.clone();
+            broken[i] ^= 0x81;
+            if let Ok((other, _)) = Trie::try_deserialize_from_slice(&broken) {
+                for key in &keys {
+                    other.exact_match(key.chars());
+                    other.common_prefix_search(key.chars()).count();
+                }
+                other.iter().count();
+            }
+        }
+    }
+
+    #[test]
+    fn test_serialize_payloads() {
+        let records = vec![
+            ("世界", "せかい"),
+            ("世界中", "せかいじゅう"),
+            ("国民", "こくみん"),
+        ];
+        let trie = Builder::new()
+            .build_from_payloads(records.clone())
+            .unwrap()
+            .release_trie()
+            .unwrap();
+
+        let mut bytes = trie.serialize_to_vec();
+        assert_eq!(trie.io_bytes(), bytes.len());
+        #[cfg(feature = "std")]
+        {
+            let mut buf = vec![];
+            trie.write_serialized(&mut buf).unwrap();
+            assert_eq!(buf, bytes);
+        }
+        bytes.push(42);
+
+        let (other, remain) = Trie::deserialize_from_slice(&bytes);
+        assert_eq!(remain, &[42]);
+        assert_eq!(trie.payloads, other.payloads);
+        let (other, remain) = Trie::try_deserialize_from_slice(&bytes).unwrap();
+        assert_eq!(remain, &[42]);
+        for (key, reading) in &records {
+            assert_eq!(other.payload(key.chars()), Some(reading.as_bytes()));
+        }
+        for len in 0..trie.io_bytes() {
+            assert!(Trie::try_deserialize_from_slice(&bytes[..len]).is_err());
+        }
     }
 
-    /// Returns the number of vacant elements.
-    ///
-    /// # Note
-    ///
-    /// It takes `O(num_elems)` time.
-    pub fn num_vacants(&self) -> usize {
-        self.nodes.iter().filter(|nd| nd.is_vacant()).count()
+    #[test]
+    fn test_extend_payloads() {
+        let mut trie = Builder::new()
+            .build_from_payloads([("世界", "せかい"), ("国民", "こくみん")])
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        trie.extend([("世界中", 0)]);
+        assert_eq!(trie.payload("世界".chars()), Some("せかい".as_bytes()));
+        assert_eq!(trie.payload("世界中".chars()), Some("せかい".as_bytes()));
+        assert_eq!(trie.payload("国民".chars()), Some("こくみん".as_bytes()));
     }
-}
 
-/// Iterator for common prefix search.
-pub struct CommonPrefixSearchIter<'t, I> {
-    haystack: I,
-    haystack_pos: usize,
-    trie: &'t Trie,
-    node_idx: u32,
-}
+    #[test]
+    fn test_migrate() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        let bytes = trie.serialize_to_vec();
 
-impl<I> Iterator for CommonPrefixSearchIter<'_, I>
-where
-    I: Iterator<Item = char>,
-{
-    type Item = (u32, usize);
+        // The format of version 1 is the current one without the header and payloads.
+        let legacy = &bytes[format::HEADER_BYTES..bytes.len() - 4];
+        let e = Trie::try_deserialize_from_slice(legacy).err().unwrap();
+        assert!(matches!(
+            e,
+            crate::errors::CrawdadError::UnsupportedVersion(ref e)
+                if e.found() == format::LEGACY_VERSION && e.supported() == format::FORMAT_VERSION
+        ));
+        let (other, remain) = Trie::migrate_from_slice(legacy).unwrap();
+        assert!(remain.is_empty());
+        assert_eq!(other.serialize_to_vec(), bytes);
 
-    #[inline(always)]
-    fn next(&mut self) -> Option<Self::Item> {
-        for c in self.haystack.by_ref() {
-            let mc = self.trie.mapper.get(c)?;
-            self.node_idx = self.trie.get_child_idx(self.node_idx, mc)?;
-            self.haystack_pos += 1;
-            if self.trie.is_leaf(self.node_idx) {
-                return Some((self.trie.get_value(self.node_idx), self.haystack_pos));
-            } else if self.trie.has_leaf(self.node_idx) {
-                let leaf_idx = self.trie.get_leaf_idx(self.node_idx);
-                return Some((self.trie.get_value(leaf_idx), self.haystack_pos));
-            }
+        let (other, remain) = Trie::migrate_from_slice(&bytes).unwrap();
+        assert!(remain.is_empty());
+        assert_eq!(other.serialize_to_vec(), bytes);
+
+        // A future version is rejected even by the migration.
+        let mut future = bytes.clone();
+        future[4..8].copy_from_slice(&(format::FORMAT_VERSION + 1).to_le_bytes());
+        for result in [
+            Trie::try_deserialize_from_slice(&future),
+            Trie::migrate_from_slice(&future),
+        ] {
+            let e = result.err().unwrap();
+            assert!(matches!(
+                e,
+                crate::errors::CrawdadError::UnsupportedVersion(ref e)
+                    if e.found() == format::FORMAT_VERSION + 1
+            ));
         }
-        None
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_io_bytes() {
+        let key_sets: [&[&str]; 4] = [
+            &["世界", "国民"],
+            &["a", "ab", "abc"],
+            &["世界", "世界中", "世論調査", "統計調査"],
+            &["\0", "\u{10ffff}", "🦞", "crawdad"],
+        ];
+        for keys in key_sets {
+            let trie = Trie::from_keys(keys).unwrap();
+            assert_eq!(trie.io_bytes(), trie.serialize_to_vec().len());
+        }
+    }
 
     #[test]
-    fn test_exact_match() {
-        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+    fn test_empty_set() {
+        assert!(Trie::from_keys(&[""][0..0]).is_err());
+    }
+
+    #[test]
+    fn test_empty_char() {
+        assert!(Trie::from_keys([""]).is_err());
+    }
+
+    #[test]
+    fn test_empty_key() {
+        assert!(Trie::from_keys(["", "AAA"]).is_err());
+    }
+
+    #[test]
+    fn test_unsorted_keys() {
+        assert!(Trie::from_keys(["BB", "AA"]).is_ok());
+        assert!(Trie::from_keys(["AAA", "AA"]).is_ok());
+    }
+
+    #[test]
+    fn test_from_str_slice() {
+        let keys = ["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_str_slice(&keys).unwrap();
+        assert_eq!(
+            trie.serialize_to_vec(),
+            Trie::from_keys(keys).unwrap().serialize_to_vec()
+        );
+        assert_eq!(trie.exact_match("世論調査".chars()), Some(2));
+        assert!(Trie::from_str_slice(&[]).is_err());
+        assert!(Trie::from_str_slice(&["世界", ""]).is_err());
+    }
+
+    #[test]
+    fn test_unsorted_keys_keep_input_positions() {
+        let keys = vec![
+            "統計調査",
+            "世界中",
+            "国民",
+            "世",
+            "世論調査",
+            "世界",
+            "市民",
+            "国",
+        ];
         let trie = Trie::from_keys(&keys).unwrap();
         for (i, key) in keys.iter().enumerate() {
             assert_eq!(
@@ -343,69 +3713,670 @@ mod tests {
                 Some(u32::try_from(i).unwrap())
             );
         }
-        assert_eq!(trie.exact_match("世".chars()), None);
-        assert_eq!(trie.exact_match("世論".chars()), None);
-        assert_eq!(trie.exact_match("世界中で".chars()), None);
-        assert_eq!(trie.exact_match("統計".chars()), None);
-        assert_eq!(trie.exact_match("統計調".chars()), None);
-        assert_eq!(trie.exact_match("日本".chars()), None);
+        let mut sorted = keys.clone();
+        sorted.sort_unstable();
+        let positions: Vec<_> = sorted
+            .iter()
+            .map(|key| u32::try_from(keys.iter().position(|k| k == key).unwrap()).unwrap())
+            .collect();
+        assert_eq!(trie.values().collect::<Vec<_>>(), positions);
     }
 
     #[test]
-    fn test_common_prefix_search() {
+    fn test_duplicate_keys() {
+        assert!(Trie::from_keys(["AA", "AA"]).is_err());
+    }
+
+    #[test]
+    fn test_exact_match_node() {
+        let records = vec![("世界", 1), ("世界中", 2), ("世論調査", 1), ("統計調査", 3)];
+        let trie = Trie::from_records(records.clone()).unwrap();
+        for &(key, value) in &records {
+            let node_idx = trie.exact_match_node(key.chars()).unwrap();
+            assert!(trie.is_leaf(node_idx));
+            assert_eq!(trie.get_value(node_idx), value);
+            assert_eq!(trie.leaf_value(node_idx), Some(value));
+        }
+        assert_eq!(trie.exact_match_node("世".chars()), None);
+        assert_eq!(trie.exact_match_node("世界中で".chars()), None);
+        assert_eq!(trie.leaf_value(0), None);
+        assert_eq!(trie.leaf_value(u32::MAX), None);
+    }
+
+    #[test]
+    fn test_same_entry() {
+        let records = vec![("世界", 1), ("世界中", 2), ("世論調査", 1), ("統計調査", 3)];
+        let trie = Trie::from_records(records).unwrap();
+        assert!(trie.same_entry("世界".chars(), "世界".chars()));
+        assert!(trie.same_entry("世界中".chars(), "世界中".chars()));
+        assert!(!trie.same_entry("世界".chars(), "世論調査".chars()));
+        assert!(!trie.same_entry("世界".chars(), "世界中".chars()));
+        assert!(!trie.same_entry("世".chars(), "世".chars()));
+        assert!(!trie.same_entry("日本".chars(), "世界".chars()));
+    }
+
+    #[test]
+    fn test_keys_and_values() {
         let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
-        let trie = Trie::from_keys(&keys).unwrap();
+        let values = vec![3, 1, 4, 1];
+        let trie = Builder::new()
+            .build_from_keys_and_values(&keys, &values)
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        let other = Trie::from_records(keys.iter().zip(values.iter().copied())).unwrap();
+        assert_eq!(trie.mapper, other.mapper);
+        assert_eq!(trie.nodes, other.nodes);
+    }
 
-        let haystack: Vec<_> = "世界中の統計世論調査".chars().collect();
-        let mut matches = vec![];
+    #[test]
+    fn test_keys_and_values_mismatch() {
+        let keys = vec!["世界", "世界中", "世論調査"];
+        let values = vec![3, 1];
+        assert!(Builder::new()
+            .build_from_keys_and_values(&keys, &values)
+            .is_err());
+    }
 
-        for i in 0..haystack.len() {
-            for (v, j) in trie.common_prefix_search(haystack[i..].iter().copied()) {
-                matches.push((v, i..i + j));
+    #[test]
+    fn test_streaming_builder() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let mut builder = Builder::new().streaming();
+        for (i, key) in keys.iter().enumerate() {
+            builder.push(key, u32::try_from(i).unwrap()).unwrap();
+        }
+        assert_eq!(builder.len(), keys.len());
+        let trie = builder.finish().unwrap();
+        let other = Trie::from_keys(&keys).unwrap();
+        assert_eq!(trie.mapper, other.mapper);
+        assert_eq!(trie.nodes, other.nodes);
+    }
+
+    #[test]
+    fn test_streaming_builder_unsorted() {
+        let mut builder = Builder::new().streaming();
+        builder.push("世界", 0).unwrap();
+        builder.push("世界中", 1).unwrap();
+        for key in ["世界", "世", "世界中"] {
+            match builder.push(key, 2) {
+                Err(crate::errors::CrawdadError::UnsortedKey(e)) => {
+                    assert_eq!((e.index(), e.key(), e.prev_key()), (2, key, "世界中"));
+                    assert_eq!(
+                        e.to_string(),
+                        format!(
+                            "UnsortedKeyError: the 2-th key {:?} is not greater than \
+                             the previous key \"世界中\"",
+                            key
+                        )
+                    );
+                }
+                _ => panic!(),
             }
         }
-        assert_eq!(matches, vec![(0, 0..2), (1, 0..3), (2, 6..10)]);
+        assert!(builder.push("", 2).is_err());
+        builder.push("国民", 2).unwrap();
+        assert_eq!(builder.len(), 3);
+        let trie = builder.finish().unwrap();
+        assert_eq!(trie.exact_match("国民".chars()), Some(2));
     }
 
     #[test]
-    fn test_serialize() {
+    fn test_keep_keys() {
+        let keys = vec!["世", "世界", "世界中", "世論調査", "統計調査"];
+        let trie = Builder::new()
+            .keep_keys()
+            .build_from_keys(&keys)
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        for (key, value) in trie.iter() {
+            assert_eq!(trie.key(value), Some(key.as_str()));
+        }
+        assert_eq!(trie.key(5), None);
+
+        let other = Trie::from_keys(&keys).unwrap();
+        assert!(other.heap_bytes() < trie.heap_bytes());
+        assert_eq!(other.key(0), None);
+        assert_eq!(trie.nodes, other.nodes);
+    }
+
+    #[test]
+    fn test_keep_keys_sparse_values() {
+        let records = vec![("世界", 1 << 28), ("国民", MAX_VALUE)];
+        let trie = Builder::new()
+            .keep_keys()
+            .build_from_records(records.clone())
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        assert_eq!(trie.key(1 << 28), Some("世界"));
+        assert_eq!(trie.key(MAX_VALUE), Some("国民"));
+        assert_eq!(trie.key(0), None);
+
+        let other = Trie::from_records(records).unwrap();
+        assert!(trie.heap_bytes() - other.heap_bytes() < 64);
+    }
+
+    #[test]
+    fn test_for_each_common_prefix() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+
+        let mut matches = vec![];
+        trie.for_each_common_prefix("世界中国".chars(), |v, j| {
+            matches.push((v, j));
+            ControlFlow::Continue(())
+        });
+        assert_eq!(matches, vec![(0, 2), (1, 3)]);
+
+        let mut num_calls = 0;
+        trie.for_each_common_prefix("世界中国".chars(), |_, _| {
+            num_calls += 1;
+            ControlFlow::Break(())
+        });
+        assert_eq!(num_calls, 1);
+    }
+
+    #[test]
+    fn test_next_chars() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+
+        assert_eq!(trie.next_chars("".chars()), vec!['世', '統']);
+        assert_eq!(trie.next_chars("世".chars()), vec!['界', '論']);
+        assert_eq!(trie.next_chars("世界".chars()), vec!['中']);
+        assert_eq!(trie.next_chars("世論".chars()), vec!['調']);
+        assert!(trie.next_chars("世界中".chars()).is_empty());
+        assert!(trie.next_chars("世界中国".chars()).is_empty());
+        assert!(trie.next_chars("日本".chars()).is_empty());
+    }
+
+    #[test]
+    fn test_exact_match_profiled() {
         let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
         let trie = Trie::from_keys(&keys).unwrap();
 
+        for key in &keys {
+            let (value, stats) = trie.exact_match_profiled(key.chars());
+            assert_eq!(value, trie.exact_match(key.chars()));
+            assert_eq!(stats.nodes_visited, key.chars().count());
+            assert_eq!(stats.chars_consumed, key.chars().count());
+            assert_eq!(stats.end_marker_followed, *key == "世界");
+        }
+
+        let (value, stats) = trie.exact_match_profiled("世論国家".chars());
+        assert_eq!(value, None);
+        assert_eq!(stats.nodes_visited, 2);
+        assert_eq!(stats.chars_consumed, 3);
+
+        let (value, stats) = trie.exact_match_profiled("世論".chars());
+        assert_eq!(value, None);
+        assert_eq!(stats.nodes_visited, 2);
+        assert_eq!(stats.chars_consumed, 2);
+        assert!(!stats.end_marker_followed);
+    }
+
+    #[test]
+    fn test_exact_match_with_len() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査", "a", "ab"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(
+                trie.exact_match_with_len(key.chars()),
+                Some((u32::try_from(i).unwrap(), key.chars().count()))
+            );
+        }
+        assert_eq!(trie.exact_match_with_len("世論".chars()), None);
+        assert_eq!(trie.exact_match_with_len("世論調査中".chars()), None);
+    }
+
+    #[test]
+    fn test_extreme_key_with_prefix() {
+        let keys = vec![
+            "世界",
+            "世界中",
+            "世論調査",
+            "統計調査",
+            "世",
+            "世論",
+            "統計",
+            "統計学",
+            "a",
+            "ab",
+            "abc",
+        ];
+        let trie = Trie::from_keys(&keys).unwrap();
+        let records: Vec<_> = trie.iter().collect();
+        for prefix in [
+            "",
+            "世",
+            "世界",
+            "世界中",
+            "世論",
+            "世論調",
+            "統",
+            "統計",
+            "a",
+            "ab",
+            "abc",
+            "x",
+            "世論調査中",
+        ] {
+            let under = || {
+                records
+                    .iter()
+                    .filter(|(k, _)| k.starts_with(prefix))
+                    .cloned()
+            };
+            assert_eq!(trie.min_key_with_prefix(prefix.chars()), under().min());
+            assert_eq!(trie.max_key_with_prefix(prefix.chars()), under().max());
+        }
+    }
+
+    #[test]
+    fn test_union_and_intersection() {
+        let a =
+            Trie::from_records([("世界", 0), ("世界中", 1), ("世論調査", 2), ("統計", 5)]).unwrap();
+        let b =
+            Trie::from_records([("世界中", 3), ("世論", 4), ("統計", 1), ("統計調査", 6)]).unwrap();
+
+        let union: Vec<_> = a.union(&b, u32::max).unwrap().iter().collect();
+        let expected = vec![
+            ("世界".to_string(), 0),
+            ("世界中".to_string(), 3),
+            ("世論".to_string(), 4),
+            ("世論調査".to_string(), 2),
+            ("統計".to_string(), 5),
+            ("統計調査".to_string(), 6),
+        ];
+        assert_eq!(union, expected);
+
+        let intersection: Vec<_> = a.intersection(&b, |_, v| v).unwrap().iter().collect();
+        let expected = vec![("世界中".to_string(), 3), ("統計".to_string(), 1)];
+        assert_eq!(intersection, expected);
+
+        let c = Trie::from_keys(["国民"]).unwrap();
+        assert!(a.intersection(&c, u32::max).is_err());
+    }
+
+    #[test]
+    fn test_flat_u32() {
+        let keys = vec!["世", "世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        let (nodes, table) = trie.to_flat_u32();
+        assert_eq!(nodes.len(), trie.num_elems() * 2);
+
+        let other = Trie::from_flat_u32(&nodes, &table).unwrap();
+        assert_eq!(other.serialize_to_vec(), trie.serialize_to_vec());
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(
+                other.exact_match(key.chars()),
+                Some(u32::try_from(i).unwrap())
+            );
+        }
+        assert_eq!(other.exact_match("世論".chars()), None);
+
+        assert!(matches!(
+            Trie::from_flat_u32(&nodes[1..], &table),
+            Err(crate::errors::CrawdadError::Corrupted(_))
+        ));
+        assert!(matches!(
+            Trie::from_flat_u32(&nodes[..nodes.len() - 2], &table),
+            Err(crate::errors::CrawdadError::Corrupted(_))
+        ));
+        assert!(Trie::from_flat_u32(&nodes, &[]).is_err());
+    }
+
+    #[test]
+    fn test_flat_u32_identity_mapping() {
+        let trie = Builder::new()
+            .identity_mapping()
+            .build_from_keys(["ab", "abc", "b"])
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        let (nodes, table) = trie.to_flat_u32();
+        let other = Trie::from_flat_u32(&nodes, &table).unwrap();
+        assert_eq!(other.serialize_to_vec(), trie.serialize_to_vec());
+        assert_eq!(other.exact_match("abc".chars()), Some(1));
+    }
+
+    #[test]
+    fn test_clear() {
+        let keys = vec!["世", "世界", "世界中", "世論調査", "統計調査"];
+        let mut trie = Builder::new()
+            .keep_keys()
+            .build_from_keys(&keys)
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        let capacity = trie.nodes.capacity();
+        trie.clear();
+        assert_eq!(trie.nodes.capacity(), capacity);
+
+        for key in keys.iter().chain(&["", "日本"]) {
+            assert_eq!(trie.exact_match(key.chars()), None);
+            assert_eq!(trie.common_prefix_search(key.chars()).count(), 0);
+            assert_eq!(trie.min_key_with_prefix(key.chars()), None);
+        }
+        assert!(trie.next_chars("".chars()).is_empty());
+        assert_eq!(trie.iter().count(), 0);
+        assert_eq!(trie.key(0), None);
+        assert_eq!(trie.num_leaves(), 0);
+        assert!(trie.validate().is_err());
+
+        trie.extend([("国民", 3)]);
+        assert_eq!(trie.exact_match("国民".chars()), Some(3));
+        assert!(trie.validate().is_ok());
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut trie = Trie::from_records([("世界", 0), ("世論調査", 2), ("統計", 5)]).unwrap();
+        trie.extend([("世界中", 1), ("世", 3), ("統計", 4)]);
+        trie.extend(vec![("統計調査".to_string(), 6), ("世".to_string(), 7)]);
+        trie.extend(Vec::<(&str, u32)>::new());
+        let expected = vec![
+            ("世".to_string(), 7),
+            ("世界".to_string(), 0),
+            ("世界中".to_string(), 1),
+            ("世論調査".to_string(), 2),
+            ("統計".to_string(), 4),
+            ("統計調査".to_string(), 6),
+        ];
+        assert_eq!(trie.iter().collect::<Vec<_>>(), expected);
+        for (key, value) in &expected {
+            assert_eq!(trie.exact_match(key.chars()), Some(*value));
+        }
+        assert_eq!(trie.exact_match("世論".chars()), None);
+    }
+
+    #[test]
+    fn test_extend_keep_keys() {
+        let mut trie = Builder::new()
+            .keep_keys()
+            .build_from_keys(["世界", "国民"])
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        trie.extend([("世界中", 2)]);
+        assert_eq!(trie.key(2), Some("世界中"));
+        assert_eq!(trie.key(1), Some("国民"));
+    }
+
+    #[test]
+    fn test_extend_options() {
+        let records = [("世界", 0), ("世界中", 1), ("世論調査", 2), ("統計", 3)];
+        let builders = [
+            Builder::new().identity_mapping(),
+            Builder::new().code_order(crate::CodeOrder::ByCodepoint),
+            Builder::new().keep_keys().max_key_chars(4),
+        ];
+        for builder in builders {
+            let mut trie = builder
+                .clone()
+                .build_from_records(records[..2].iter().copied())
+                .unwrap()
+                .release_trie()
+                .unwrap();
+            trie.extend(records[2..].iter().copied());
+            let expected = builder
+                .build_from_records(records)
+                .unwrap()
+                .release_trie()
+                .unwrap();
+            assert_eq!(trie.options, expected.options);
+            assert_eq!(trie.serialize_to_vec(), expected.serialize_to_vec());
+        }
+
+        // Values of new records are not shifted by the offset.
+        let mut trie = Builder::new()
+            .value_offset(10)
+            .build_from_keys(["世界"])
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        trie.extend([("国民", 1)]);
+        assert_eq!(trie.exact_match("世界".chars()), Some(10));
+        assert_eq!(trie.exact_match("国民".chars()), Some(1));
+
+        // A deserialized trie is rebuilt with the default options.
         let bytes = trie.serialize_to_vec();
-        assert_eq!(trie.io_bytes(), bytes.len());
+        let (mut other, _) = Trie::deserialize_from_slice(&bytes);
+        other.extend([("統計", 2)]);
+        assert_eq!(other.options, BuildOptions::default());
+    }
 
-        let (other, remain) = Trie::deserialize_from_slice(&bytes);
-        assert!(remain.is_empty());
+    #[test]
+    fn test_scan() {
+        let keys = vec!["世", "世界", "世界中", "世論調査", "統計調査", "調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        let haystack: Vec<char> = "世界中の統計調査と世論調査".chars().collect();
+        let mut expected = vec![];
+        for i in 0..haystack.len() {
+            for (v, j) in trie.common_prefix_search(haystack[i..].iter().copied()) {
+                expected.push((v, i..i + j));
+            }
+        }
+        assert_eq!(trie.scan(&haystack).collect::<Vec<_>>(), expected);
+        assert_eq!(
+            expected,
+            vec![
+                (0, 0..1),
+                (1, 0..2),
+                (2, 0..3),
+                (4, 4..8),
+                (5, 6..8),
+                (0, 9..10),
+                (3, 9..13),
+                (5, 11..13)
+            ]
+        );
+        // Every range is distinct, even for keys inside longer keys.
+        for w in expected.windows(2) {
+            assert!((w[0].1.start, w[0].1.end) < (w[1].1.start, w[1].1.end));
+        }
+        assert_eq!(trie.scan(&[]).count(), 0);
+        assert_eq!(trie.scan(&['日', '本']).count(), 0);
+    }
 
-        assert_eq!(trie.mapper, other.mapper);
-        assert_eq!(trie.nodes, other.nodes);
+    #[test]
+    fn test_scan_str() {
+        let keys = vec![
+            "世",
+            "世界",
+            "世界中",
+            "世論調査",
+            "統計調査",
+            "調査",
+            "aé",
+            "é",
+        ];
+        let trie = Trie::from_keys(&keys).unwrap();
+        for text in [
+            "世界中の統計調査と世論調査",
+            "aé世界",
+            "éaé調査",
+            "",
+            "日本",
+        ] {
+            let haystack: Vec<char> = text.chars().collect();
+            let offsets: Vec<_> = text
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain([text.len()])
+                .collect();
+            let expected: Vec<_> = trie
+                .scan(&haystack)
+                .map(|(v, r)| (v, offsets[r.start]..offsets[r.end]))
+                .collect();
+            let matches: Vec<_> = trie.scan_str(text).collect();
+            assert_eq!(matches, expected);
+            for (v, r) in matches {
+                assert_eq!(&text[r], keys[usize::try_from(v).unwrap()]);
+            }
+        }
+        // A match ending at the end of the text.
+        assert_eq!(
+            trie.scan_str("aé").collect::<Vec<_>>(),
+            vec![(6, 0..3), (7, 1..3)]
+        );
     }
 
     #[test]
-    fn test_empty_set() {
-        assert!(Trie::from_keys(&[""][0..0]).is_err());
+    fn test_common_prefix_search_with_keys() {
+        let keys = vec!["世", "世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        let haystack: Vec<char> = "世界中の統計調査と世論調査".chars().collect();
+        for i in 0..haystack.len() {
+            let suffix = haystack[i..].iter().copied();
+            let expected: Vec<_> = trie
+                .common_prefix_search(suffix.clone())
+                .map(|(v, j)| (haystack[i..i + j].iter().collect::<String>(), v, j))
+                .collect();
+            let matches: Vec<_> = trie.common_prefix_search_with_keys(suffix).collect();
+            assert_eq!(matches, expected);
+            for (key, v, _) in matches {
+                assert_eq!(keys[usize::try_from(v).unwrap()], key);
+            }
+        }
     }
 
     #[test]
-    fn test_empty_char() {
-        assert!(Trie::from_keys([""]).is_err());
+    fn test_update_value() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let mut trie = Builder::new()
+            .keep_keys()
+            .build_from_keys(&keys)
+            .unwrap()
+            .release_trie()
+            .unwrap();
+
+        assert_eq!(trie.update_value("世界".chars(), 2).unwrap(), Some(0));
+        assert_eq!(trie.update_value("世界中".chars(), 7).unwrap(), Some(1));
+        assert_eq!(trie.update_value("世論".chars(), 7).unwrap(), None);
+        assert!(trie
+            .update_value("統計調査".chars(), MAX_VALUE + 1)
+            .is_err());
+
+        assert_eq!(trie.exact_match("世界".chars()), Some(2));
+        assert_eq!(trie.exact_match("世界中".chars()), Some(7));
+        assert_eq!(trie.exact_match("世論調査".chars()), Some(2));
+        assert_eq!(trie.exact_match("統計調査".chars()), Some(3));
+        assert!(trie.validate().is_ok());
+
+        assert_eq!(trie.key(0), None);
+        assert_eq!(trie.key(1), None);
+        assert_eq!(trie.key(2), Some("世界"));
+        assert_eq!(trie.key(7), Some("世界中"));
+        let records: Vec<_> = trie.iter_borrowed().unwrap().collect();
+        assert_eq!(
+            records,
+            vec![("世界", 2), ("世界中", 7), ("世論調査", 2), ("統計調査", 3)]
+        );
+
+        assert_eq!(trie.update_value("世界".chars(), 3).unwrap(), Some(2));
+        assert_eq!(trie.key(2), Some("世論調査"));
+        assert_eq!(trie.key(3), Some("世界"));
     }
 
     #[test]
-    fn test_empty_key() {
-        assert!(Trie::from_keys(["", "AAA"]).is_err());
+    fn test_validate() {
+        let keys = vec!["世界", "世界中", "世論調査", "統計調査"];
+        let trie = Trie::from_keys(&keys).unwrap();
+        assert!(trie.validate().is_ok());
+
+        let corrupt = |f: &dyn Fn(&mut Trie)| {
+            let mut other = Trie::deserialize_from_slice(&trie.serialize_to_vec()).0;
+            f(&mut other);
+            other.validate()
+        };
+        let leaf_idx = trie.exact_match_node("世界中".chars()).unwrap();
+        let leaf = usize::try_from(leaf_idx).unwrap();
+        // "世界" is a prefix of "世界中" and is terminated with END_MARKER.
+        let end_leaf = usize::try_from(trie.exact_match_node("世界".chars()).unwrap()).unwrap();
+        let parent = usize::try_from(trie.nodes[end_leaf].get_check()).unwrap();
+
+        // Clears the leaf flag.
+        assert!(corrupt(&|t| t.nodes[leaf].base &= OFFSET_MASK).is_err());
+        assert!(corrupt(&|t| t.nodes[end_leaf].base &= OFFSET_MASK).is_err());
+        // Clears the has-leaf flag.
+        assert!(corrupt(&|t| t.nodes[parent].check &= OFFSET_MASK).is_err());
+        // Points to a parent out of bounds.
+        let len = u32::try_from(trie.nodes.len()).unwrap();
+        assert!(corrupt(&|t| t.nodes[leaf].check = len).is_err());
+        // Moves children out of bounds.
+        assert!(corrupt(&|t| t.nodes[0].base = len).is_err());
+        // Truncates the array.
+        assert!(corrupt(&|t| t.nodes.truncate(1)).is_err());
+        // Points to a parent that is a leaf.
+        assert!(corrupt(&|t| t.nodes[parent].check = leaf_idx).is_err());
     }
 
     #[test]
-    fn test_unsorted_keys() {
-        assert!(Trie::from_keys(["BB", "AA"]).is_ok());
-        assert!(Trie::from_keys(["AAA", "AA"]).is_ok());
+    fn test_iter_borrowed() {
+        let records = vec![
+            ("世界", 3),
+            ("世界中", 1),
+            ("世論調査", 4),
+            ("統計調査", 1),
+            ("世", 5),
+        ];
+        let trie = Builder::new()
+            .keep_keys()
+            .build_from_records(records)
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        let borrowed: Vec<_> = trie
+            .iter_borrowed()
+            .unwrap()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+        assert_eq!(borrowed, trie.iter().collect::<Vec<_>>());
+
+        let other = Trie::from_keys(["世界"]).unwrap();
+        assert!(other.iter_borrowed().is_none());
     }
 
     #[test]
-    fn test_duplicate_keys() {
-        assert!(Trie::from_keys(["AA", "AA"]).is_err());
+    fn test_identity_mapping() {
+        let keys = vec!["ab", "abc", "b", "bcd"];
+        let trie = Builder::new()
+            .identity_mapping()
+            .build_from_keys(&keys)
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        assert_eq!(trie.mapper.alphabet_size(), 129);
+        assert_eq!(trie.mapper.get('a'), Some(u32::from('a') + 1));
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(
+                trie.exact_match(key.chars()),
+                Some(u32::try_from(i).unwrap())
+            );
+        }
+        assert_eq!(trie.exact_match("bc".chars()), None);
+        assert_eq!(trie.exact_match("\u{ffff}".chars()), None);
+    }
+
+    #[test]
+    fn test_identity_mapping_fallback() {
+        let keys = vec!["ab", "abc", "世界", "世界中"];
+        let trie = Builder::new()
+            .identity_mapping()
+            .build_from_keys(&keys)
+            .unwrap()
+            .release_trie()
+            .unwrap();
+        assert_eq!(trie.mapper.alphabet_size(), 7);
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(
+                trie.exact_match(key.chars()),
+                Some(u32::try_from(i).unwrap())
+            );
+        }
+        assert_eq!(trie.exact_match("世".chars()), None);
     }
 
     #[test]
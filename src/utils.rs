@@ -2,6 +2,11 @@ use core::cmp::Ordering;
 
 use alloc::vec::Vec;
 
+use crate::errors::{CrawdadError, Result};
+
+// The smallest page size of common platforms, used to touch each page once.
+const PAGE_SIZE: usize = 4096;
+
 /// Returns the smallest number of bytes that can encode `n`.
 #[inline(always)]
 pub const fn pack_size(n: u32) -> u8 {
@@ -30,6 +35,34 @@ pub fn unpack_u32(slice: &[u8], nbytes: u8) -> u32 {
     u32::from_le_bytes(n_array)
 }
 
+/// Splits the head `n` bytes off `source`, or returns an error if it is too short.
+#[inline(always)]
+pub fn split_bytes<'a>(source: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+    if source.len() < n {
+        return Err(CrawdadError::corrupted("source is too short."));
+    }
+    let (head, tail) = source.split_at(n);
+    *source = tail;
+    Ok(head)
+}
+
+/// Splits a little-endian `u32` off `source`, or returns an error if it is too short.
+#[inline(always)]
+pub fn split_u32(source: &mut &[u8]) -> Result<u32> {
+    split_bytes(source, 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Splits `len` elements of `size` bytes off `source` without allocating for them in advance,
+/// or returns an error if it is too short.
+#[inline(always)]
+pub fn split_array<'a>(source: &mut &'a [u8], len: u32, size: usize) -> Result<&'a [u8]> {
+    let n = usize::try_from(len)
+        .ok()
+        .and_then(|len| len.checked_mul(size))
+        .ok_or_else(|| CrawdadError::corrupted("source is too short."))?;
+    split_bytes(source, n)
+}
+
 /// Returns `(lcp, ord)` such that
 ///  - lcp: Length of longest commom prefix of `a` and `b`.
 ///  - ord: `Ordering` between `a` and `b`.
@@ -44,6 +77,19 @@ pub fn longest_common_prefix(a: &[char], b: &[char]) -> (usize, Ordering) {
     (min_len, a.len().cmp(&b.len()))
 }
 
+/// Reads an element in every page of `slice` so that the pages are loaded into memory.
+#[inline(never)]
+pub fn touch_pages<T: Copy>(slice: &[T]) {
+    let step = (PAGE_SIZE / core::mem::size_of::<T>().max(1)).max(1);
+    for x in slice.iter().step_by(step) {
+        // SAFETY: `x` is a valid reference, and the volatile read prevents the compiler
+        // from eliding the access.
+        unsafe {
+            core::ptr::read_volatile(x);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
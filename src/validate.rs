@@ -0,0 +1,146 @@
+use crate::errors::{CrawdadError, Result};
+use crate::mapper::CodeMapper;
+use crate::traverse::ChildTable;
+use crate::{utils, Node, END_CODE, MAX_VALUE, OFFSET_MASK};
+
+/// Checks the invariants of the double array shared by both trie forms.
+pub fn validate_nodes(mapper: &CodeMapper, nodes: &[Node]) -> Result<()> {
+    let alphabet_size = mapper.alphabet_size();
+    if alphabet_size == 0 || OFFSET_MASK < alphabet_size {
+        return Err(CrawdadError::corrupted("alphabet size is out of range."));
+    }
+    if !mapper.is_closed() {
+        return Err(CrawdadError::corrupted(
+            "mapper assigns a code out of the alphabet.",
+        ));
+    }
+
+    let block_len = alphabet_size.next_power_of_two().max(2);
+    let num_nodes = u32::try_from(nodes.len())
+        .ok()
+        .filter(|&n| n != 0 && n <= OFFSET_MASK && n % block_len == 0)
+        .ok_or_else(|| CrawdadError::corrupted("number of nodes is inconsistent with blocks."))?;
+
+    let root = &nodes[0];
+    if root.is_vacant() || root.is_leaf() || root.check != OFFSET_MASK {
+        return Err(CrawdadError::corrupted("root node is broken."));
+    }
+
+    let mut num_used = 1;
+    for (i, node) in nodes.iter().enumerate() {
+        if node.is_vacant() {
+            continue;
+        }
+        let node_idx = u32::try_from(i).unwrap();
+        if !node.is_leaf() {
+            // Every child must be placed in the same block.
+            if num_nodes <= node.get_base() | (block_len - 1) {
+                return Err(CrawdadError::corrupted(
+                    "base of a node points out of bounds.",
+                ));
+            }
+            if node.has_leaf() {
+                let leaf_idx = node.get_base() ^ END_CODE;
+                if nodes[usize::try_from(leaf_idx).unwrap()].check != node_idx {
+                    return Err(CrawdadError::corrupted(
+                        "has-leaf flag is set without a leaf.",
+                    ));
+                }
+            }
+        }
+        if node_idx == 0 {
+            continue;
+        }
+        num_used += 1;
+
+        let parent_idx = node.get_check();
+        let parent = nodes
+            .get(usize::try_from(parent_idx).unwrap())
+            .filter(|parent| !parent.is_vacant())
+            .ok_or_else(|| CrawdadError::corrupted("check of a node points to no parent."))?;
+        if parent.is_leaf() {
+            return Err(CrawdadError::corrupted("check of a node points to a leaf."));
+        }
+        let code = parent.get_base() ^ node_idx;
+        if alphabet_size <= code {
+            return Err(CrawdadError::corrupted(
+                "label of a node is out of the alphabet.",
+            ));
+        }
+        if code == END_CODE {
+            if !node.is_leaf() {
+                return Err(CrawdadError::corrupted("terminal node is not a leaf."));
+            }
+            if !parent.has_leaf() {
+                return Err(CrawdadError::corrupted(
+                    "has-leaf flag is unset for a leaf.",
+                ));
+            }
+        }
+    }
+
+    // Every used node must be reachable from the root, i.e., no check forms a cycle.
+    let table = ChildTable::new(nodes, &mapper.decoder());
+    let mut stack = vec![0];
+    let mut num_reached = 0;
+    while let Some(node_idx) = stack.pop() {
+        num_reached += 1;
+        let children = table.children(node_idx);
+        if children.is_empty() && !nodes[usize::try_from(node_idx).unwrap()].is_leaf() {
+            return Err(CrawdadError::corrupted("internal node has no children."));
+        }
+        stack.extend_from_slice(children);
+    }
+    if num_reached != num_used {
+        return Err(CrawdadError::corrupted(
+            "some nodes are unreachable from the root.",
+        ));
+    }
+    Ok(())
+}
+
+/// Checks if the tail of every leaf in the minimal-prefix form can be decoded.
+pub fn validate_tails(
+    mapper: &CodeMapper,
+    nodes: &[Node],
+    tails: &[u8],
+    code_size: u8,
+    value_size: u8,
+) -> Result<()> {
+    if !(1..=4).contains(&code_size) || !(1..=4).contains(&value_size) {
+        return Err(CrawdadError::corrupted("packing size is out of range."));
+    }
+    let code_size = usize::from(code_size);
+    for (i, node) in nodes.iter().enumerate().skip(1) {
+        if node.is_vacant() || !node.is_leaf() {
+            continue;
+        }
+        // The leaf indicated with END_CODE holds its value instead of a tail.
+        let parent = &nodes[usize::try_from(node.get_check()).unwrap()];
+        if parent.has_leaf() && usize::try_from(parent.get_base() ^ END_CODE).unwrap() == i {
+            continue;
+        }
+        let tail_pos = usize::try_from(node.get_base()).unwrap();
+        let tail_len = usize::from(
+            *tails
+                .get(tail_pos)
+                .ok_or_else(|| CrawdadError::corrupted("tail of a leaf is out of bounds."))?,
+        );
+        let value_pos = tail_pos + 1 + tail_len * code_size;
+        if tails.len() < value_pos + usize::from(value_size) {
+            return Err(CrawdadError::corrupted("tail of a leaf is out of bounds."));
+        }
+        for pos in (tail_pos + 1..value_pos).step_by(code_size) {
+            let code = utils::unpack_u32(&tails[pos..], u8::try_from(code_size).unwrap());
+            if code == END_CODE || mapper.alphabet_size() <= code {
+                return Err(CrawdadError::corrupted(
+                    "tail of a leaf has an invalid code.",
+                ));
+            }
+        }
+        if MAX_VALUE < utils::unpack_u32(&tails[value_pos..], value_size) {
+            return Err(CrawdadError::corrupted("value of a leaf is out of range."));
+        }
+    }
+    Ok(())
+}
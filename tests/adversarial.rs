@@ -0,0 +1,63 @@
+use crawdad::{Builder, MpTrie, Trie};
+
+fn check(keys: &[String]) {
+    let trie = Trie::from_keys(keys).unwrap();
+    let mptrie = MpTrie::from_keys(keys).unwrap();
+    assert!(trie.validate().is_ok());
+    assert!(mptrie.validate().is_ok());
+    for (i, key) in keys.iter().enumerate() {
+        let i = u32::try_from(i).unwrap();
+        assert_eq!(trie.exact_match(key.chars()), Some(i));
+        assert_eq!(mptrie.exact_match(key.chars()), Some(i));
+    }
+    let mut expected: Vec<_> = keys.iter().cloned().zip(0..).collect();
+    expected.sort_unstable();
+    assert_eq!(trie.iter().collect::<Vec<_>>(), expected);
+    assert_eq!(mptrie.iter().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn test_long_common_prefix() {
+    let prefix = "あ".repeat(200);
+    let keys: Vec<_> = (0..10000).map(|i| format!("{}{}", prefix, i)).collect();
+    check(&keys);
+}
+
+#[test]
+fn test_wide_root() {
+    // Every character in the BMP except surrogates and END_MARKER.
+    let keys: Vec<_> = (1..0xffff)
+        .filter_map(char::from_u32)
+        .map(String::from)
+        .collect();
+    check(&keys);
+}
+
+#[test]
+fn test_deep_chain() {
+    let key: String = (0..5000)
+        .map(|i| char::from_u32(0x4e00 + i % 64).unwrap())
+        .collect();
+    let keys: Vec<_> = key
+        .char_indices()
+        .map(|(i, c)| key[..i + c.len_utf8()].to_string())
+        .step_by(7)
+        .collect();
+    check(&keys);
+}
+
+#[test]
+fn test_single_key() {
+    check(&["世界".to_string()]);
+    check(&["a".to_string()]);
+    for min in 0..3 {
+        let trie = Builder::new()
+            .minimal_prefix_len(min)
+            .build_from_keys(["世界"])
+            .unwrap()
+            .release_mptrie()
+            .unwrap();
+        assert_eq!(trie.exact_match("世界".chars()), Some(0));
+        assert!(trie.validate().is_ok());
+    }
+}
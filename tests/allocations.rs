@@ -0,0 +1,46 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crawdad::Trie;
+
+struct CountingAllocator;
+
+static NUM_ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        NUM_ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        NUM_ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+#[test]
+fn test_borrowed_records_allocations() {
+    let keys: Vec<String> = (0..1000).map(|i| format!("key{:012}", i)).collect();
+    let records: Vec<(Cow<str>, u32)> = keys
+        .iter()
+        .enumerate()
+        .map(|(i, k)| (Cow::Borrowed(k.as_str()), u32::try_from(i).unwrap()))
+        .collect();
+
+    let before = NUM_ALLOCS.load(Ordering::Relaxed);
+    let trie = Trie::from_records(records).unwrap();
+    let num_allocs = NUM_ALLOCS.load(Ordering::Relaxed) - before;
+
+    // One allocation per key for its characters, plus a few for the arrays.
+    assert!(num_allocs < keys.len() + 100, "{}", num_allocs);
+    assert_eq!(trie.exact_match("key000000000123".chars()), Some(123));
+}